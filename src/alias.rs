@@ -0,0 +1,453 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Literal, Type, ValueOps};
+
+use crate::{
+    analysis_manager::AnalysisManager,
+    analyze::{DataFlowAnalysis, Definition},
+    error::Result,
+    parse::{
+        block_name_to_idx, expanded_basic_blocks, get_block_name, BasicBlock, ControlFlowGraph,
+    },
+};
+
+// past this many distinct constant offsets into one allocation, the offset is treated as unknown
+const MAX_OFFSETS: usize = 8;
+
+// abstract memory location a pointer variable may refer to
+#[derive(Debug, Eq, Hash, PartialEq, Clone)]
+pub enum Location {
+    // `alloc` instruction and offset from the start of its allocation (None if not constant)
+    Site(Definition, Option<i64>),
+    // memory that was not allocated by this function, or a pointer loaded from memory
+    Unknown,
+}
+
+// the value a pointer is computed from through `id` and constant `ptradd`
+#[derive(Debug, Eq, Hash, PartialEq, Clone)]
+enum Root {
+    Definition(Definition),
+    Argument(String),
+}
+
+pub fn is_pointer(t: &Type) -> bool {
+    matches!(t, Type::Pointer(_))
+}
+
+pub struct AliasAnalysis {
+    blocks: Vec<BasicBlock>,
+    block_map: HashMap<String, usize>,
+    reaching: Rc<DataFlowAnalysis>,
+    points_to: HashMap<String, HashSet<Location>>, // of every definition of the variable
+    def_points_to: HashMap<Definition, HashSet<Location>>, // of pointer definitions
+    escaped: HashSet<Definition>, // allocation sites whose pointers may be reached through `Unknown`
+    constants: HashMap<String, i64>,
+    args: HashSet<String>,
+    cyclic: HashSet<String>, // blocks that lie on a cycle of the CFG
+}
+
+impl AliasAnalysis {
+    pub fn new(func: &Function, analyses: &mut AnalysisManager) -> Result<AliasAnalysis> {
+        let blocks = expanded_basic_blocks(func);
+        let block_map = block_name_to_idx(func);
+
+        // every definition in the function with its location
+        let defs: Vec<(Definition, Instruction)> = blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(block_idx, block)| {
+                let block_name = get_block_name(block, block_idx, &func.name);
+                block
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(line, code)| match code {
+                        Code::Instruction(instr @ Instruction::Constant { dest, .. })
+                        | Code::Instruction(instr @ Instruction::Value { dest, .. }) => Some((
+                            Definition {
+                                name: dest.clone(),
+                                block: block_name.clone(),
+                                line,
+                            },
+                            instr.clone(),
+                        )),
+                        _ => None,
+                    })
+            })
+            .collect();
+
+        let args: HashSet<String> = func.args.iter().map(|arg| arg.name.clone()).collect();
+        let constants = Self::constant_vars(&defs, &args);
+
+        // points-to sets of definitions and of variables, iterated to a fixed point. The arguments
+        // of a definition may come from any definition of the argument variables.
+        let mut def_points_to: HashMap<Definition, HashSet<Location>> = HashMap::new();
+        let mut points_to: HashMap<String, HashSet<Location>> = func
+            .args
+            .iter()
+            .filter(|arg| is_pointer(&arg.arg_type))
+            .map(|arg| (arg.name.clone(), HashSet::from([Location::Unknown])))
+            .collect();
+        loop {
+            let mut changed = false;
+            for (def, instr) in defs.iter() {
+                if let Instruction::Value {
+                    args, op, op_type, ..
+                } = instr
+                {
+                    if !is_pointer(op_type) {
+                        continue;
+                    }
+                    let locs: HashSet<Location> = match op {
+                        ValueOps::Alloc => HashSet::from([Location::Site(def.clone(), Some(0))]),
+                        ValueOps::Id | ValueOps::Phi => args
+                            .iter()
+                            .flat_map(|arg| points_to.get(arg).cloned().unwrap_or_default())
+                            .collect(),
+                        ValueOps::PtrAdd => points_to
+                            .get(&args[0])
+                            .cloned()
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|loc| match loc {
+                                Location::Site(site, Some(offset)) => Location::Site(
+                                    site,
+                                    constants.get(&args[1]).and_then(|k| offset.checked_add(*k)),
+                                ),
+                                loc => loc,
+                            })
+                            .collect(),
+                        // loads and calls can produce any pointer
+                        _ => HashSet::from([Location::Unknown]),
+                    };
+
+                    let def_entry = def_points_to.entry(def.clone()).or_default();
+                    *def_entry = Self::widen(def_entry.union(&locs).cloned().collect());
+                    let entry = points_to.entry(def.name.clone()).or_default();
+                    let merged = Self::widen(entry.union(&locs).cloned().collect());
+                    if merged != *entry {
+                        *entry = merged;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        // allocations whose pointers are stored to memory, passed to calls or returned
+        let escaped: HashSet<Definition> = blocks
+            .iter()
+            .flatten()
+            .flat_map(|code| -> Vec<String> {
+                match code {
+                    Code::Instruction(Instruction::Effect {
+                        args,
+                        op: EffectOps::Store,
+                        ..
+                    }) => vec![args[1].clone()],
+                    Code::Instruction(Instruction::Effect {
+                        args,
+                        op: EffectOps::Call | EffectOps::Return,
+                        ..
+                    })
+                    | Code::Instruction(Instruction::Value {
+                        args,
+                        op: ValueOps::Call,
+                        ..
+                    }) => args.clone(),
+                    _ => vec![],
+                }
+            })
+            .flat_map(|var| points_to.get(&var).cloned().unwrap_or_default())
+            .filter_map(|loc| match loc {
+                Location::Site(site, _) => Some(site),
+                Location::Unknown => None,
+            })
+            .collect();
+
+        let successors = analyses.control_flow_graph(func)?;
+        Ok(AliasAnalysis {
+            reaching: analyses.reaching_definitions(func)?,
+            cyclic: Self::cyclic_blocks(&successors),
+            blocks,
+            block_map,
+            points_to,
+            def_points_to,
+            escaped,
+            constants,
+            args,
+        })
+    }
+
+    // variables whose every definition is the same integer constant
+    fn constant_vars(
+        defs: &[(Definition, Instruction)],
+        args: &HashSet<String>,
+    ) -> HashMap<String, i64> {
+        let mut constants: HashMap<String, Option<i64>> = HashMap::new();
+        for (def, instr) in defs {
+            let value = match instr {
+                Instruction::Constant {
+                    value: Literal::Int(value),
+                    ..
+                } => Some(*value),
+                _ => None,
+            };
+            let entry = constants.entry(def.name.clone()).or_insert(value);
+            if *entry != value {
+                *entry = None;
+            }
+        }
+        constants
+            .into_iter()
+            .filter(|(var, _)| !args.contains(var))
+            .filter_map(|(var, value)| Some((var, value?)))
+            .collect()
+    }
+
+    // bound the number of offsets tracked per allocation so that the fixed point terminates
+    fn widen(locs: HashSet<Location>) -> HashSet<Location> {
+        let mut offsets: HashMap<Definition, usize> = HashMap::new();
+        let mut unknown_offset: HashSet<Definition> = HashSet::new();
+        for loc in locs.iter() {
+            match loc {
+                Location::Site(site, Some(_)) => *offsets.entry(site.clone()).or_default() += 1,
+                Location::Site(site, None) => {
+                    unknown_offset.insert(site.clone());
+                }
+                Location::Unknown => {}
+            }
+        }
+        unknown_offset.extend(
+            offsets
+                .into_iter()
+                .filter(|(_, count)| *count > MAX_OFFSETS)
+                .map(|(site, _)| site),
+        );
+
+        locs.into_iter()
+            .map(|loc| match loc {
+                Location::Site(site, _) if unknown_offset.contains(&site) => {
+                    Location::Site(site, None)
+                }
+                loc => loc,
+            })
+            .collect()
+    }
+
+    fn cyclic_blocks(successors: &ControlFlowGraph) -> HashSet<String> {
+        successors
+            .keys()
+            .filter(|&block| {
+                let mut visited: HashSet<&String> = HashSet::new();
+                let mut stack: Vec<&String> = successors[block].iter().collect();
+                while let Some(cur) = stack.pop() {
+                    if cur == block {
+                        return true;
+                    }
+                    if visited.insert(cur) {
+                        stack.extend(successors[cur].iter());
+                    }
+                }
+                false
+            })
+            .cloned()
+            .collect()
+    }
+
+    // abstract locations `var` may point to anywhere in the function
+    pub fn points_to(&self, var: &String) -> HashSet<Location> {
+        self.points_to
+            .get(var)
+            .cloned()
+            .unwrap_or_else(|| HashSet::from([Location::Unknown]))
+    }
+
+    fn overlap(&self, loc0: &Location, loc1: &Location, whole_allocation: bool) -> bool {
+        match (loc0, loc1) {
+            (Location::Site(site0, offset0), Location::Site(site1, offset1)) => {
+                site0 == site1
+                    && match (offset0, offset1) {
+                        (Some(offset0), Some(offset1)) if !whole_allocation => offset0 == offset1,
+                        _ => true,
+                    }
+            }
+            (Location::Site(site, _), Location::Unknown)
+            | (Location::Unknown, Location::Site(site, _)) => self.escaped.contains(site),
+            (Location::Unknown, Location::Unknown) => true,
+        }
+    }
+
+    // Abstract locations `var` may point to right before `line` of `block`: those of the
+    // definitions of `var` that reach there. Arguments and loaded pointers may point anywhere.
+    pub fn points_to_at(&self, var: &String, block: &String, line: usize) -> HashSet<Location> {
+        let defs = self.reaching_at(var, block, line);
+        if defs.is_empty() || self.args.contains(var) {
+            return self.points_to(var);
+        }
+        defs.iter()
+            .flat_map(|def| {
+                self.def_points_to
+                    .get(def)
+                    .cloned()
+                    .unwrap_or_else(|| HashSet::from([Location::Unknown]))
+            })
+            .collect()
+    }
+
+    fn may_overlap(
+        &self,
+        ptr0: &String,
+        ptr1: &String,
+        block: &String,
+        line: usize,
+        whole_allocation: bool,
+    ) -> bool {
+        let locs1 = self.points_to_at(ptr1, block, line);
+        self.points_to_at(ptr0, block, line).iter().any(|loc0| {
+            locs1
+                .iter()
+                .any(|loc1| self.overlap(loc0, loc1, whole_allocation))
+        })
+    }
+
+    // whether the two pointers can refer to the same memory cell right before `line` of `block`
+    pub fn may_alias(&self, ptr0: &String, ptr1: &String, block: &String, line: usize) -> bool {
+        self.may_overlap(ptr0, ptr1, block, line, false)
+    }
+
+    // whether the two pointers can point into the same allocation right before `line` of `block`
+    pub fn may_alias_allocation(
+        &self,
+        ptr0: &String,
+        ptr1: &String,
+        block: &String,
+        line: usize,
+    ) -> bool {
+        self.may_overlap(ptr0, ptr1, block, line, true)
+    }
+
+    // whether the two pointers refer to the same memory cell right before `line` of `block`
+    pub fn must_alias(&self, ptr0: &String, ptr1: &String, block: &String, line: usize) -> bool {
+        self.same_root(ptr0, ptr1, block, line, false)
+    }
+
+    // whether the two pointers point into the same allocation right before `line` of `block`
+    pub fn must_alias_allocation(
+        &self,
+        ptr0: &String,
+        ptr1: &String,
+        block: &String,
+        line: usize,
+    ) -> bool {
+        self.same_root(ptr0, ptr1, block, line, true)
+    }
+
+    fn same_root(
+        &self,
+        ptr0: &String,
+        ptr1: &String,
+        block: &String,
+        line: usize,
+        whole_allocation: bool,
+    ) -> bool {
+        if ptr0 == ptr1 {
+            return true;
+        }
+        let resolved0 = self.resolve(ptr0, block, line, &mut HashSet::new());
+        let resolved1 = self.resolve(ptr1, block, line, &mut HashSet::new());
+        match (resolved0, resolved1) {
+            (Some((root0, offset0)), Some((root1, offset1))) => {
+                root0 == root1
+                    && (whole_allocation || offset0 == offset1)
+                    && self.executes_once(&root0)
+            }
+            _ => false,
+        }
+    }
+
+    // a root inside a loop may hold a different value each iteration
+    fn executes_once(&self, root: &Root) -> bool {
+        match root {
+            Root::Definition(def) => !self.cyclic.contains(&def.block),
+            Root::Argument(_) => true,
+        }
+    }
+
+    // definitions of `var` that reach right before `line` of `block`
+    pub fn reaching_at(&self, var: &String, block: &String, line: usize) -> HashSet<Definition> {
+        let mut defs: HashSet<Definition> = self.reaching[block]
+            .0
+            .iter()
+            .filter(|def| &def.name == var)
+            .cloned()
+            .collect();
+        for (def_line, code) in self.blocks[self.block_map[block]]
+            .iter()
+            .enumerate()
+            .take(line)
+        {
+            if let Code::Instruction(
+                Instruction::Constant { dest, .. } | Instruction::Value { dest, .. },
+            ) = code
+            {
+                if dest == var {
+                    defs = HashSet::from([Definition {
+                        name: dest.clone(),
+                        block: block.clone(),
+                        line: def_line,
+                    }]);
+                }
+            }
+        }
+        defs
+    }
+
+    // follow `id` copies and constant `ptradd`s back to the value a pointer was computed from
+    fn resolve(
+        &self,
+        var: &String,
+        block: &String,
+        line: usize,
+        visited: &mut HashSet<Definition>,
+    ) -> Option<(Root, i64)> {
+        let defs = self.reaching_at(var, block, line);
+        if self.args.contains(var) {
+            // reaching definitions do not model arguments, so a redefined argument is ambiguous
+            return if defs.is_empty() {
+                Some((Root::Argument(var.clone()), 0))
+            } else {
+                None
+            };
+        }
+        if defs.len() != 1 {
+            return None;
+        }
+        let def = defs.into_iter().next()?;
+        if !visited.insert(def.clone()) {
+            return None;
+        }
+
+        match &self.blocks[self.block_map[&def.block]][def.line] {
+            Code::Instruction(Instruction::Value {
+                args,
+                op: ValueOps::Id,
+                ..
+            }) => self.resolve(&args[0], &def.block, def.line, visited),
+            Code::Instruction(Instruction::Value {
+                args,
+                op: ValueOps::PtrAdd,
+                ..
+            }) => {
+                let offset = self.constants.get(&args[1])?;
+                let (root, base_offset) = self.resolve(&args[0], &def.block, def.line, visited)?;
+                Some((root, base_offset.checked_add(*offset)?))
+            }
+            _ => Some((Root::Definition(def), 0)),
+        }
+    }
+}
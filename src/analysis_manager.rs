@@ -0,0 +1,197 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use bril_rs::{Function, Program};
+
+use crate::{
+    analyze::{
+        dominance_frontier_of, dominator_tree_of, dominators_of, live_variables_of,
+        natural_loops_of, reaching_definitions_of, DataFlowAnalysis, LiveVariables, NaturalLoop,
+    },
+    error::Result,
+    parse::{control_flow_graph, ControlFlowGraph},
+    summary::{function_summaries, Summaries},
+};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Analysis {
+    ControlFlowGraph,
+    Dominators,
+    DominatorTree,
+    DominanceFrontier,
+    NaturalLoops,
+    ReachingDefinitions,
+    LiveVariables,
+}
+
+// analyses that only depend on the labels and control flow instructions of a function
+pub const CONTROL_FLOW_ANALYSES: &[Analysis] = &[
+    Analysis::ControlFlowGraph,
+    Analysis::Dominators,
+    Analysis::DominatorTree,
+    Analysis::DominanceFrontier,
+    Analysis::NaturalLoops,
+];
+
+#[derive(Default)]
+struct FunctionAnalyses {
+    cfg: Option<Rc<ControlFlowGraph>>,
+    dominators: Option<Rc<HashMap<String, HashSet<String>>>>,
+    dominator_tree: Option<Rc<HashMap<String, Vec<String>>>>,
+    dominance_frontier: Option<Rc<HashMap<String, HashSet<String>>>>,
+    natural_loops: Option<Rc<Vec<NaturalLoop>>>,
+    reaching_definitions: Option<Rc<DataFlowAnalysis>>,
+    live_variables: Option<Rc<LiveVariables>>,
+}
+
+// Caches analysis results per function name. Whoever changes a function has to call
+// `invalidate` for it, otherwise stale results are handed out. Results about the whole program
+// are for the program last passed to `set_program`.
+#[derive(Default)]
+pub struct AnalysisManager {
+    functions: HashMap<String, FunctionAnalyses>,
+    program: Option<Program>,
+    summaries: Option<Rc<Summaries>>,
+}
+
+impl AnalysisManager {
+    pub fn new() -> AnalysisManager {
+        AnalysisManager::default()
+    }
+
+    fn cached(&mut self, func: &Function) -> &mut FunctionAnalyses {
+        self.functions.entry(func.name.clone()).or_default()
+    }
+
+    pub fn control_flow_graph(&mut self, func: &Function) -> Result<Rc<ControlFlowGraph>> {
+        if let Some(cfg) = &self.cached(func).cfg {
+            return Ok(cfg.clone());
+        }
+        let cfg = Rc::new(control_flow_graph(func)?);
+        self.cached(func).cfg = Some(cfg.clone());
+        Ok(cfg)
+    }
+
+    pub fn dominators(&mut self, func: &Function) -> Result<Rc<HashMap<String, HashSet<String>>>> {
+        if let Some(dominators) = &self.cached(func).dominators {
+            return Ok(dominators.clone());
+        }
+        let cfg = self.control_flow_graph(func)?;
+        let dominators = Rc::new(dominators_of(&cfg));
+        self.cached(func).dominators = Some(dominators.clone());
+        Ok(dominators)
+    }
+
+    pub fn dominator_tree(&mut self, func: &Function) -> Result<Rc<HashMap<String, Vec<String>>>> {
+        if let Some(dominator_tree) = &self.cached(func).dominator_tree {
+            return Ok(dominator_tree.clone());
+        }
+        let cfg = self.control_flow_graph(func)?;
+        let dominators = self.dominators(func)?;
+        let dominator_tree = Rc::new(dominator_tree_of(&cfg, &dominators));
+        self.cached(func).dominator_tree = Some(dominator_tree.clone());
+        Ok(dominator_tree)
+    }
+
+    pub fn dominance_frontier(
+        &mut self,
+        func: &Function,
+    ) -> Result<Rc<HashMap<String, HashSet<String>>>> {
+        if let Some(frontier) = &self.cached(func).dominance_frontier {
+            return Ok(frontier.clone());
+        }
+        let cfg = self.control_flow_graph(func)?;
+        let dominators = self.dominators(func)?;
+        let frontier = Rc::new(dominance_frontier_of(&cfg, &dominators));
+        self.cached(func).dominance_frontier = Some(frontier.clone());
+        Ok(frontier)
+    }
+
+    pub fn natural_loops(&mut self, func: &Function) -> Result<Rc<Vec<NaturalLoop>>> {
+        if let Some(loops) = &self.cached(func).natural_loops {
+            return Ok(loops.clone());
+        }
+        let cfg = self.control_flow_graph(func)?;
+        let dominators = self.dominators(func)?;
+        let loops = Rc::new(natural_loops_of(&cfg, &dominators));
+        self.cached(func).natural_loops = Some(loops.clone());
+        Ok(loops)
+    }
+
+    pub fn reaching_definitions(&mut self, func: &Function) -> Result<Rc<DataFlowAnalysis>> {
+        if let Some(reaching) = &self.cached(func).reaching_definitions {
+            return Ok(reaching.clone());
+        }
+        let cfg = self.control_flow_graph(func)?;
+        let reaching = Rc::new(reaching_definitions_of(func, &cfg));
+        self.cached(func).reaching_definitions = Some(reaching.clone());
+        Ok(reaching)
+    }
+
+    pub fn live_variables(&mut self, func: &Function) -> Result<Rc<LiveVariables>> {
+        if let Some(live) = &self.cached(func).live_variables {
+            return Ok(live.clone());
+        }
+        let cfg = self.control_flow_graph(func)?;
+        let live = Rc::new(live_variables_of(func, &cfg));
+        self.cached(func).live_variables = Some(live.clone());
+        Ok(live)
+    }
+
+    // the program that passes are about to transform
+    pub fn set_program(&mut self, prog: &Program) {
+        if self
+            .program
+            .as_ref()
+            .is_some_and(|old| old.functions == prog.functions)
+        {
+            return;
+        }
+        self.program = Some(prog.clone());
+        self.summaries = None;
+    }
+
+    // Without a program, every function is unknown and so impure. A pass changing functions
+    // keeps the summaries sound, since it does not add effects to them.
+    pub fn function_summaries(&mut self) -> Result<Rc<Summaries>> {
+        if let Some(summaries) = &self.summaries {
+            return Ok(summaries.clone());
+        }
+        let summaries = Rc::new(match &self.program {
+            Some(prog) => function_summaries(prog)?,
+            None => Summaries::new(),
+        });
+        self.summaries = Some(summaries.clone());
+        Ok(summaries)
+    }
+
+    // drops the results for the function that are not in `preserved`
+    pub fn invalidate(&mut self, func_name: &String, preserved: &[Analysis]) {
+        let Some(analyses) = self.functions.get_mut(func_name) else {
+            return;
+        };
+        if !preserved.contains(&Analysis::ControlFlowGraph) {
+            analyses.cfg = None;
+        }
+        if !preserved.contains(&Analysis::Dominators) {
+            analyses.dominators = None;
+        }
+        if !preserved.contains(&Analysis::DominatorTree) {
+            analyses.dominator_tree = None;
+        }
+        if !preserved.contains(&Analysis::DominanceFrontier) {
+            analyses.dominance_frontier = None;
+        }
+        if !preserved.contains(&Analysis::NaturalLoops) {
+            analyses.natural_loops = None;
+        }
+        if !preserved.contains(&Analysis::ReachingDefinitions) {
+            analyses.reaching_definitions = None;
+        }
+        if !preserved.contains(&Analysis::LiveVariables) {
+            analyses.live_variables = None;
+        }
+    }
+}
@@ -6,7 +6,10 @@ use std::{
 use bril_rs::{Code, Function, Instruction};
 
 use crate::{
-    parse::{block_name_to_idx, control_flow_graph, expanded_basic_blocks},
+    error::Result,
+    parse::{
+        block_name_to_idx, control_flow_graph, expanded_basic_blocks, BasicBlock, ControlFlowGraph,
+    },
     util::{invert_digraph, invert_hashset},
 };
 
@@ -20,9 +23,12 @@ pub struct Definition {
 // maps block name to in/out sets for that block
 pub type DataFlowAnalysis = HashMap<String, (HashSet<Definition>, HashSet<Definition>)>;
 
-pub fn reaching_definitions(func: &Function) -> DataFlowAnalysis {
-    let successors = control_flow_graph(func);
-    let predecessors = invert_digraph(&successors);
+pub fn reaching_definitions(func: &Function) -> Result<DataFlowAnalysis> {
+    Ok(reaching_definitions_of(func, &control_flow_graph(func)?))
+}
+
+pub fn reaching_definitions_of(func: &Function, successors: &ControlFlowGraph) -> DataFlowAnalysis {
+    let predecessors = invert_digraph(successors);
     let blocks = expanded_basic_blocks(func);
     let block_names_to_idx: HashMap<String, usize> = block_name_to_idx(func);
     let block_names: Vec<String> = block_names_to_idx.keys().cloned().collect();
@@ -60,9 +66,7 @@ pub fn reaching_definitions(func: &Function) -> DataFlowAnalysis {
     }
 
     let mut worklist = block_names.clone();
-    while !worklist.is_empty() {
-        let b = worklist.pop().unwrap();
-
+    while let Some(b) = worklist.pop() {
         // merge
         inputs.insert(
             b.clone(),
@@ -91,9 +95,12 @@ pub fn reaching_definitions(func: &Function) -> DataFlowAnalysis {
 }
 
 // maps each block to its set of dominators
-pub fn dominators(func: &Function) -> HashMap<String, HashSet<String>> {
-    let successors = control_flow_graph(func);
-    let predecessors = invert_digraph(&successors);
+pub fn dominators(func: &Function) -> Result<HashMap<String, HashSet<String>>> {
+    Ok(dominators_of(&control_flow_graph(func)?))
+}
+
+pub fn dominators_of(successors: &ControlFlowGraph) -> HashMap<String, HashSet<String>> {
+    let predecessors = invert_digraph(successors);
     // let block_names: Vec<String> = postorder_traversal(&successors, String::from("entry"), vec![])
     // .into_iter()
     // .rev()
@@ -143,10 +150,20 @@ pub fn dominators(func: &Function) -> HashMap<String, HashSet<String>> {
     return last_dom;
 }
 
-pub fn dominance_frontier(func: &Function) -> HashMap<String, HashSet<String>> {
-    let successors = control_flow_graph(func);
-    let predecessors = invert_digraph(&successors);
-    let dom_map = invert_hashset(&dominators(func));
+pub fn dominance_frontier(func: &Function) -> Result<HashMap<String, HashSet<String>>> {
+    let successors = control_flow_graph(func)?;
+    Ok(dominance_frontier_of(
+        &successors,
+        &dominators_of(&successors),
+    ))
+}
+
+pub fn dominance_frontier_of(
+    successors: &ControlFlowGraph,
+    dominators: &HashMap<String, HashSet<String>>,
+) -> HashMap<String, HashSet<String>> {
+    let predecessors = invert_digraph(successors);
+    let dom_map = invert_hashset(dominators);
 
     dom_map
         .iter()
@@ -169,9 +186,16 @@ pub fn dominance_frontier(func: &Function) -> HashMap<String, HashSet<String>> {
 }
 
 // nodes in tree dominate all descendants
-pub fn dominator_tree(func: &Function) -> HashMap<String, Vec<String>> {
-    let predecessors = invert_digraph(&control_flow_graph(func));
-    let dominators = dominators(func);
+pub fn dominator_tree(func: &Function) -> Result<HashMap<String, Vec<String>>> {
+    let successors = control_flow_graph(func)?;
+    Ok(dominator_tree_of(&successors, &dominators_of(&successors)))
+}
+
+pub fn dominator_tree_of(
+    successors: &ControlFlowGraph,
+    dominators: &HashMap<String, HashSet<String>>,
+) -> HashMap<String, Vec<String>> {
+    let predecessors = invert_digraph(successors);
 
     dominators
         .keys()
@@ -190,3 +214,116 @@ pub fn dominator_tree(func: &Function) -> HashMap<String, Vec<String>> {
         })
         .collect()
 }
+
+// maps block name to the variables live on entry to and on exit from that block
+pub type LiveVariables = HashMap<String, (HashSet<String>, HashSet<String>)>;
+
+pub fn live_variables(func: &Function) -> Result<LiveVariables> {
+    Ok(live_variables_of(func, &control_flow_graph(func)?))
+}
+
+pub fn live_variables_of(func: &Function, successors: &ControlFlowGraph) -> LiveVariables {
+    let predecessors = invert_digraph(successors);
+    let blocks = expanded_basic_blocks(func);
+    let block_names_to_idx: HashMap<String, usize> = block_name_to_idx(func);
+
+    // variables read before being written in the block, and variables written in the block
+    let uses_defs = |block: &BasicBlock| -> (HashSet<String>, HashSet<String>) {
+        let mut used: HashSet<String> = HashSet::new();
+        let mut defined: HashSet<String> = HashSet::new();
+        for instr in block.iter() {
+            if let Code::Instruction(Instruction::Value { args, .. })
+            | Code::Instruction(Instruction::Effect { args, .. }) = instr
+            {
+                used.extend(args.iter().filter(|arg| !defined.contains(*arg)).cloned());
+            }
+            if let Code::Instruction(Instruction::Constant { dest, .. })
+            | Code::Instruction(Instruction::Value { dest, .. }) = instr
+            {
+                defined.insert(dest.clone());
+            }
+        }
+        (used, defined)
+    };
+
+    let mut inputs: HashMap<String, HashSet<String>> = successors
+        .keys()
+        .map(|b| (b.clone(), HashSet::new()))
+        .collect();
+    let mut outputs: HashMap<String, HashSet<String>> = inputs.clone();
+
+    let mut worklist: Vec<String> = successors.keys().cloned().collect();
+    while let Some(b) = worklist.pop() {
+        // merge
+        let output: HashSet<String> = successors[&b]
+            .iter()
+            .flat_map(|s| inputs[s].iter().cloned())
+            .collect();
+
+        // transfer
+        let (used, defined) = uses_defs(&blocks[block_names_to_idx[&b]]);
+        let input: HashSet<String> = used
+            .into_iter()
+            .chain(output.difference(&defined).cloned())
+            .collect();
+        outputs.insert(b.clone(), output);
+        if input != inputs[&b] {
+            worklist.append(&mut predecessors[&b].clone());
+            inputs.insert(b, input);
+        }
+    }
+
+    successors
+        .keys()
+        .map(|b| (b.clone(), (inputs[b].clone(), outputs[b].clone())))
+        .collect()
+}
+
+// A loop with a single entry: the header dominates every block of the loop, and the latches
+// jump back to the header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NaturalLoop {
+    pub header: String,
+    pub latches: Vec<String>,
+    pub blocks: HashSet<String>, // including the header
+}
+
+// back edges to the same header make up one loop, sorted by header name
+pub fn natural_loops(func: &Function) -> Result<Vec<NaturalLoop>> {
+    let successors = control_flow_graph(func)?;
+    Ok(natural_loops_of(&successors, &dominators_of(&successors)))
+}
+
+pub fn natural_loops_of(
+    successors: &ControlFlowGraph,
+    dominators: &HashMap<String, HashSet<String>>,
+) -> Vec<NaturalLoop> {
+    let predecessors = invert_digraph(successors);
+    let mut loops: HashMap<String, NaturalLoop> = HashMap::new();
+    for (latch, succs) in successors.iter() {
+        for header in succs
+            .iter()
+            .filter(|&succ| dominators[latch].contains(succ))
+        {
+            let natural_loop = loops.entry(header.clone()).or_insert_with(|| NaturalLoop {
+                header: header.clone(),
+                latches: vec![],
+                blocks: HashSet::from([header.clone()]),
+            });
+            natural_loop.latches.push(latch.clone());
+            // everything that reaches the latch without going through the header
+            let mut worklist = vec![latch.clone()];
+            while let Some(block) = worklist.pop() {
+                if natural_loop.blocks.insert(block.clone()) {
+                    worklist.extend(predecessors[&block].iter().cloned());
+                }
+            }
+        }
+    }
+    let mut loops: Vec<NaturalLoop> = loops.into_values().collect();
+    for natural_loop in loops.iter_mut() {
+        natural_loop.latches.sort();
+    }
+    loops.sort_by(|a, b| a.header.cmp(&b.header));
+    loops
+}
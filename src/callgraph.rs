@@ -0,0 +1,139 @@
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{Code, EffectOps, Instruction, Program, ValueOps};
+
+use crate::util::DiGraph;
+
+// each function to the functions it calls
+pub type CallGraph = DiGraph;
+
+// the functions called by an instruction, if it is a call
+pub fn callees(code: &Code) -> &[String] {
+    match code {
+        Code::Instruction(Instruction::Effect {
+            op: EffectOps::Call,
+            funcs,
+            ..
+        })
+        | Code::Instruction(Instruction::Value {
+            op: ValueOps::Call,
+            funcs,
+            ..
+        }) => funcs,
+        _ => &[],
+    }
+}
+
+// Every function of the program is a node. Callees are listed once, in the order they are
+// first called.
+pub fn call_graph(prog: &Program) -> CallGraph {
+    prog.functions
+        .iter()
+        .map(|func| {
+            let mut called: Vec<String> = vec![];
+            for callee in func.instrs.iter().flat_map(callees) {
+                if !called.contains(callee) {
+                    called.push(callee.clone());
+                }
+            }
+            (func.name.clone(), called)
+        })
+        .collect()
+}
+
+// Tarjan's algorithm. The components come out bottom-up: a component comes after every
+// component it calls into, so summaries of callees are ready before their callers need them.
+pub fn strongly_connected_components(graph: &CallGraph) -> Vec<Vec<String>> {
+    struct Tarjan<'a> {
+        graph: &'a CallGraph,
+        index: HashMap<&'a String, usize>,
+        lowlink: HashMap<&'a String, usize>,
+        stack: Vec<&'a String>,
+        on_stack: HashSet<&'a String>,
+        components: Vec<Vec<String>>,
+    }
+
+    impl<'a> Tarjan<'a> {
+        fn visit(&mut self, func: &'a String) {
+            let index = self.index.len();
+            self.index.insert(func, index);
+            self.lowlink.insert(func, index);
+            self.stack.push(func);
+            self.on_stack.insert(func);
+            let graph = self.graph;
+            // calls to functions outside the program, e.g. imported ones, are not followed
+            for callee in graph[func]
+                .iter()
+                .filter(|callee| graph.contains_key(*callee))
+            {
+                if !self.index.contains_key(callee) {
+                    self.visit(callee);
+                    let low = self.lowlink[func].min(self.lowlink[callee]);
+                    self.lowlink.insert(func, low);
+                } else if self.on_stack.contains(callee) {
+                    let low = self.lowlink[func].min(self.index[callee]);
+                    self.lowlink.insert(func, low);
+                }
+            }
+            if self.lowlink[func] == self.index[func] {
+                let mut component = vec![];
+                while let Some(member) = self.stack.pop() {
+                    self.on_stack.remove(member);
+                    component.push(member.clone());
+                    if member == func {
+                        break;
+                    }
+                }
+                component.sort();
+                self.components.push(component);
+            }
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        graph,
+        index: HashMap::new(),
+        lowlink: HashMap::new(),
+        stack: vec![],
+        on_stack: HashSet::new(),
+        components: vec![],
+    };
+    // sort to make the order deterministic
+    let mut funcs: Vec<&String> = graph.keys().collect();
+    funcs.sort();
+    for func in funcs {
+        if !tarjan.index.contains_key(func) {
+            tarjan.visit(func);
+        }
+    }
+    tarjan.components
+}
+
+// every function after the functions it calls, except within a cycle of recursive calls
+pub fn bottom_up_order(graph: &CallGraph) -> Vec<String> {
+    strongly_connected_components(graph)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+// functions that may call themselves, directly or through other functions
+pub fn recursive_functions(graph: &CallGraph) -> HashSet<String> {
+    strongly_connected_components(graph)
+        .into_iter()
+        .filter(|component| component.len() > 1 || graph[&component[0]].contains(&component[0]))
+        .flatten()
+        .collect()
+}
+
+// functions that can be called, directly or indirectly, from the roots, including the roots
+pub fn reachable_functions(graph: &CallGraph, roots: &[String]) -> HashSet<String> {
+    let mut reachable: HashSet<String> = HashSet::new();
+    let mut stack: Vec<&String> = roots.iter().collect();
+    while let Some(func) = stack.pop() {
+        if reachable.insert(func.clone()) {
+            stack.extend(graph.get(func).into_iter().flatten());
+        }
+    }
+    reachable
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+
+use bril_rs::Program;
+
+use crate::{
+    error::{Error, Result},
+    interp::{interpret, interpret_limited, Execution},
+};
+
+// executions of a program before and after a transformation, on the same arguments
+pub struct Comparison {
+    pub original: Result<Execution>,
+    pub transformed: Result<Execution>,
+}
+
+pub fn compare(original: &Program, transformed: &Program, args: &[String]) -> Comparison {
+    Comparison {
+        original: interpret(original, args),
+        transformed: interpret(transformed, args),
+    }
+}
+
+// same as `compare`, but each execution gives up after `max_instructions` instructions
+pub fn compare_limited(
+    original: &Program,
+    transformed: &Program,
+    args: &[String],
+    max_instructions: u64,
+) -> Comparison {
+    Comparison {
+        original: interpret_limited(original, args, max_instructions),
+        transformed: interpret_limited(transformed, args, max_instructions),
+    }
+}
+
+// The same kind of error, or no error. A transformation may legitimately move where an error
+// happens, so only what went wrong is compared.
+fn same_failure(original: Option<&Error>, transformed: Option<&Error>) -> bool {
+    match (original, transformed) {
+        (
+            Some(Error::Runtime { msg: original, .. }),
+            Some(Error::Runtime {
+                msg: transformed, ..
+            }),
+        ) => original == transformed,
+        (Some(original), Some(transformed)) => {
+            std::mem::discriminant(original) == std::mem::discriminant(transformed)
+        }
+        (original, transformed) => original.is_none() && transformed.is_none(),
+    }
+}
+
+impl Comparison {
+    // Both printed the same lines, and then returned the same value or failed the same way. If
+    // both ran out of instructions, only the lines both printed have to be the same, since either
+    // might have printed the rest had it kept going.
+    pub fn agrees(&self) -> bool {
+        match (&self.original, &self.transformed) {
+            (Ok(original), Ok(transformed)) if original.gave_up || transformed.gave_up => {
+                original.gave_up
+                    && transformed.gave_up
+                    && (original.output.starts_with(&transformed.output)
+                        || transformed.output.starts_with(&original.output))
+            }
+            (Ok(original), Ok(transformed)) => {
+                original.output == transformed.output
+                    && original.return_value == transformed.return_value
+                    && same_failure(original.failure.as_ref(), transformed.failure.as_ref())
+            }
+            (Err(original), Err(transformed)) => same_failure(Some(original), Some(transformed)),
+            _ => false,
+        }
+    }
+}
+
+fn describe(f: &mut fmt::Formatter<'_>, name: &str, execution: &Result<Execution>) -> fmt::Result {
+    match execution {
+        Ok(Execution {
+            failure: Some(err), ..
+        })
+        | Err(err) => writeln!(f, "{}: error: {}", name, err),
+        Ok(execution) => writeln!(f, "{}: {} instructions", name, execution.instructions),
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        describe(f, "original", &self.original)?;
+        describe(f, "transformed", &self.transformed)?;
+        if self.agrees() {
+            return write!(f, "ok");
+        }
+        let (Ok(original), Ok(transformed)) = (&self.original, &self.transformed) else {
+            if self.original.is_err() && self.transformed.is_err() {
+                return write!(f, "mismatch: the programs cannot run for different reasons");
+            }
+            return write!(f, "mismatch: only one program can run");
+        };
+        if original.gave_up != transformed.gave_up {
+            return write!(f, "mismatch: only one execution ran out of instructions");
+        }
+        let lines = original.output.len().max(transformed.output.len());
+        if let Some(line) =
+            (0..lines).find(|&i| original.output.get(i) != transformed.output.get(i))
+        {
+            let printed = |output: &Vec<String>| match output.get(line) {
+                Some(text) => format!("`{}`", text),
+                None => String::from("nothing"),
+            };
+            return write!(
+                f,
+                "mismatch: line {} of the output is {} originally, {} after transforming",
+                line + 1,
+                printed(&original.output),
+                printed(&transformed.output)
+            );
+        }
+        if !same_failure(original.failure.as_ref(), transformed.failure.as_ref()) {
+            let ended = |execution: &Execution| match &execution.failure {
+                Some(Error::Runtime { msg, .. }) => format!("fails with `{}`", msg),
+                Some(err) => format!("fails with `{}`", err),
+                None => String::from("finishes"),
+            };
+            return write!(
+                f,
+                "mismatch: {} originally, {} after transforming",
+                ended(original),
+                ended(transformed)
+            );
+        }
+        let returned = |execution: &Execution| match execution.return_value {
+            Some(value) => value.to_string(),
+            None => String::from("nothing"),
+        };
+        write!(
+            f,
+            "mismatch: returns {} originally, {} after transforming",
+            returned(original),
+            returned(transformed)
+        )
+    }
+}
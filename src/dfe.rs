@@ -0,0 +1,60 @@
+use bril_rs::{Function, Program};
+
+use crate::{
+    analysis_manager::AnalysisManager,
+    callgraph::{call_graph, reachable_functions},
+    error::{Error, Result},
+    pass::Pass,
+};
+
+// removes the functions that cannot be called from the roots
+pub struct DeadFunctionElim {
+    roots: Vec<String>,
+}
+
+impl DeadFunctionElim {
+    pub fn new(roots: Vec<String>) -> DeadFunctionElim {
+        DeadFunctionElim { roots }
+    }
+}
+
+impl Pass for DeadFunctionElim {
+    fn name(&self) -> &str {
+        "dfe"
+    }
+
+    // reports the removed functions on stderr like the dfe mode does
+    fn run_on_program(&self, prog: &Program, _analyses: &mut AnalysisManager) -> Result<Program> {
+        let (new_prog, removed) = dead_function_elim(prog, &self.roots)?;
+        for name in removed {
+            eprintln!("removed @{}", name);
+        }
+        Ok(new_prog)
+    }
+}
+
+// The program without the functions unreachable from `roots` in the call graph, and the names
+// of the removed functions in program order.
+pub fn dead_function_elim(prog: &Program, roots: &[String]) -> Result<(Program, Vec<String>)> {
+    if let Some(root) = roots
+        .iter()
+        .find(|&root| !prog.functions.iter().any(|func| &func.name == root))
+    {
+        return Err(Error::InvalidPipeline(format!(
+            "no function @{} to keep functions reachable from",
+            root
+        )));
+    }
+    let reachable = reachable_functions(&call_graph(prog), roots);
+    let mut new_prog = prog.clone();
+    let (kept, removed): (Vec<Function>, Vec<Function>) = prog
+        .functions
+        .iter()
+        .cloned()
+        .partition(|func| reachable.contains(&func.name));
+    new_prog.functions = kept;
+    Ok((
+        new_prog,
+        removed.into_iter().map(|func| func.name).collect(),
+    ))
+}
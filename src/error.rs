@@ -0,0 +1,71 @@
+use std::fmt;
+
+use bril_rs::Position;
+
+use crate::util::position_string;
+
+// everything that can go wrong when analyzing or transforming a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    // a jump or branch to a label the function does not define
+    UnknownLabel {
+        func: String,
+        label: String,
+        pos: Option<Position>,
+    },
+    UndefinedVariable {
+        func: String,
+        var: String,
+        pos: Option<Position>,
+    },
+    // any other instruction that is not well formed, e.g. has the wrong number of arguments
+    Malformed {
+        func: String,
+        msg: String,
+        pos: Option<Position>,
+    },
+    // the program failed while being interpreted, e.g. divided by zero or leaked memory
+    Runtime {
+        func: String,
+        msg: String,
+        pos: Option<Position>,
+    },
+    InvalidPipeline(String),
+    // the program was no longer well formed after running the pass
+    AfterPass(String, Box<Error>),
+    Io(String),
+    // imports that cannot be resolved, or files that define the same function
+    Link(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownLabel { func, label, pos } => write!(
+                f,
+                "{}: in @{}: jump to undefined label .{}",
+                position_string(pos),
+                func,
+                label
+            ),
+            Error::UndefinedVariable { func, var, pos } => write!(
+                f,
+                "{}: in @{}: use of undefined variable {}",
+                position_string(pos),
+                func,
+                var
+            ),
+            Error::Malformed { func, msg, pos } | Error::Runtime { func, msg, pos } => {
+                write!(f, "{}: in @{}: {}", position_string(pos), func, msg)
+            }
+            Error::InvalidPipeline(msg) | Error::Io(msg) | Error::Link(msg) => {
+                write!(f, "{}", msg)
+            }
+            Error::AfterPass(pass, error) => write!(f, "after pass '{}': {}", pass, error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,114 @@
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+};
+
+use bril_rs::Program;
+
+use crate::{check::compare_limited, generate::random_program, pass::PassManager};
+
+// generated programs terminate, but loops nested in loops can still take a while
+pub const MAX_INSTRUCTIONS: u64 = 100_000;
+
+// every pass on its own, then the combinations the presets use
+pub const FUZZ_PIPELINES: [&str; 18] = [
+    "lvn",
+    "fold",
+    "dce",
+    "dse",
+    "ssa",
+    "mem2reg",
+    "memdse",
+    "inline",
+    "dfe",
+    "specialize",
+    "tce",
+    "unroll",
+    "sr",
+    "lvn,dce,dse",
+    "fold,dce,dse",
+    "ssa,[fold,dce]",
+    "mem2reg,memdse,dce",
+    "inline,fold,dce",
+];
+
+// a generated program that a pipeline broke
+pub struct Failure {
+    pub seed: u64,
+    pub pipeline: String,
+    pub reason: String,
+    pub prog: Program,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seed {}: pipeline '{}': {}\n{}",
+            self.seed, self.pipeline, self.reason, self.prog
+        )
+    }
+}
+
+// runs `f`, turning a panic into its message
+pub fn catch_panic<T>(f: impl FnOnce() -> T) -> std::result::Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        if let Some(msg) = payload.downcast_ref::<&str>() {
+            String::from(*msg)
+        } else if let Some(msg) = payload.downcast_ref::<String>() {
+            msg.clone()
+        } else {
+            String::from("unknown panic")
+        }
+    })
+}
+
+// runs `f` without the default hook printing every caught panic to stderr
+pub fn quietly<T>(f: impl FnOnce() -> T) -> T {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = f();
+    panic::set_hook(hook);
+    result
+}
+
+// Why the pipeline breaks the program: it panics, fails, leaves the program malformed or changes
+// what it prints or returns. None if the program survives the pipeline.
+pub fn pipeline_failure(prog: &Program, pipeline: &str) -> Option<String> {
+    let manager = match PassManager::parse(pipeline, true) {
+        Ok(manager) => manager,
+        Err(err) => return Some(err.to_string()),
+    };
+    let new_prog = match catch_panic(|| manager.run(prog)) {
+        Ok(Ok(new_prog)) => new_prog,
+        Ok(Err(err)) => return Some(format!("pipeline failed: {}", err)),
+        Err(msg) => return Some(format!("panicked: {}", msg)),
+    };
+    match catch_panic(|| compare_limited(prog, &new_prog, &[], MAX_INSTRUCTIONS)) {
+        Ok(comparison) if comparison.agrees() => None,
+        Ok(comparison) => Some(comparison.to_string().replace('\n', "; ")),
+        Err(msg) => Some(format!("interpreter panicked: {}", msg)),
+    }
+}
+
+// Runs every fuzz pipeline on the programs generated from seeds `first_seed..first_seed + count`
+// and collects the first failure of each program.
+pub fn fuzz(first_seed: u64, count: u64) -> Vec<Failure> {
+    // the panics are reported as failures, so the hook need not print them too
+    quietly(|| {
+        (first_seed..first_seed.saturating_add(count))
+            .filter_map(|seed| {
+                let prog = random_program(seed);
+                let (pipeline, reason) = FUZZ_PIPELINES.iter().find_map(|pipeline| {
+                    pipeline_failure(&prog, pipeline).map(|reason| (pipeline, reason))
+                })?;
+                Some(Failure {
+                    seed,
+                    pipeline: String::from(*pipeline),
+                    reason,
+                    prog,
+                })
+            })
+            .collect()
+    })
+}
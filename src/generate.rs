@@ -0,0 +1,435 @@
+use bril_rs::{
+    Argument, Code, ConstOps, EffectOps, Function, Instruction, Literal, Program, Type, ValueOps,
+};
+
+// how deeply branches and loops nest
+const MAX_DEPTH: usize = 3;
+// statements per straight-line stretch of code
+const MAX_STATEMENTS: usize = 6;
+const MAX_FUNCTIONS: usize = 3;
+const MAX_LOOP_TRIPS: i64 = 4;
+const MAX_ALLOC: i64 = 4;
+
+// deterministic xorshift generator, so a seed always reproduces the same program
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // the state must never be zero
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // uniform in 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+// signature of a function that later functions may call
+struct Callee {
+    name: String,
+    arg_types: Vec<Type>,
+    return_type: Option<Type>,
+}
+
+struct Generator<'a> {
+    rng: &'a mut Rng,
+    callees: &'a [Callee],
+    instrs: Vec<Code>,
+    // variables that are defined on every path to the current point
+    ints: Vec<String>,
+    bools: Vec<String>,
+    next_var: usize,
+    next_label: usize,
+}
+
+// Generates a well-typed program that terminates: loops have constant trip counts, functions
+// only call functions defined before them, and every allocation is initialized before it is
+// read and freed before the function returns.
+pub fn random_program(seed: u64) -> Program {
+    let mut rng = Rng::new(seed);
+    let mut callees: Vec<Callee> = vec![];
+    let mut functions = vec![];
+    for idx in 0..rng.below(MAX_FUNCTIONS) {
+        let args: Vec<Argument> = (0..rng.below(3))
+            .map(|i| Argument {
+                name: format!("arg{}", i),
+                arg_type: if rng.chance(70) {
+                    Type::Int
+                } else {
+                    Type::Bool
+                },
+            })
+            .collect();
+        let return_type = rng.chance(70).then_some(Type::Int);
+        let func = random_function(&mut rng, &callees, format!("f{}", idx), args, return_type);
+        callees.push(Callee {
+            name: func.name.clone(),
+            arg_types: func.args.iter().map(|arg| arg.arg_type.clone()).collect(),
+            return_type: func.return_type.clone(),
+        });
+        functions.push(func);
+    }
+    functions.push(random_function(
+        &mut rng,
+        &callees,
+        String::from("main"),
+        vec![],
+        None,
+    ));
+    Program {
+        functions,
+        imports: vec![],
+    }
+}
+
+fn random_function(
+    rng: &mut Rng,
+    callees: &[Callee],
+    name: String,
+    args: Vec<Argument>,
+    return_type: Option<Type>,
+) -> Function {
+    let mut generator = Generator {
+        rng,
+        callees,
+        instrs: vec![],
+        ints: vec![],
+        bools: vec![],
+        next_var: 0,
+        next_label: 0,
+    };
+    for arg in args.iter() {
+        match arg.arg_type {
+            Type::Int => generator.ints.push(arg.name.clone()),
+            _ => generator.bools.push(arg.name.clone()),
+        }
+    }
+    // something to compute with from the start
+    let int = generator.fresh_var();
+    let value = generator.rng.below(10) as i64;
+    generator.constant(&int, Literal::Int(value));
+    generator.ints.push(int);
+    let bool = generator.fresh_var();
+    let value = generator.rng.chance(50);
+    generator.constant(&bool, Literal::Bool(value));
+    generator.bools.push(bool);
+
+    generator.statements(0);
+    let ret_args = match return_type {
+        Some(_) => vec![generator.rng.pick(&generator.ints).clone()],
+        None => vec![],
+    };
+    generator.effect(EffectOps::Return, ret_args, vec![]);
+
+    Function {
+        args,
+        instrs: generator.instrs,
+        name,
+        pos: None,
+        return_type,
+    }
+}
+
+impl<'a> Generator<'a> {
+    fn fresh_var(&mut self) -> String {
+        self.next_var += 1;
+        format!("v{}", self.next_var)
+    }
+
+    fn fresh_label(&mut self) -> String {
+        self.next_label += 1;
+        format!("l{}", self.next_label)
+    }
+
+    fn label(&mut self, label: &String) {
+        self.instrs.push(Code::Label {
+            label: label.clone(),
+            pos: None,
+        });
+    }
+
+    fn constant(&mut self, dest: &String, value: Literal) {
+        self.instrs.push(Code::Instruction(Instruction::Constant {
+            dest: dest.clone(),
+            op: ConstOps::Const,
+            pos: None,
+            const_type: match value {
+                Literal::Int(_) => Type::Int,
+                Literal::Bool(_) => Type::Bool,
+            },
+            value,
+        }));
+    }
+
+    fn value(&mut self, dest: &String, op: ValueOps, args: Vec<String>, op_type: Type) {
+        self.value_call(dest, op, args, vec![], op_type);
+    }
+
+    fn value_call(
+        &mut self,
+        dest: &String,
+        op: ValueOps,
+        args: Vec<String>,
+        funcs: Vec<String>,
+        op_type: Type,
+    ) {
+        self.instrs.push(Code::Instruction(Instruction::Value {
+            args,
+            dest: dest.clone(),
+            funcs,
+            labels: vec![],
+            op,
+            pos: None,
+            op_type,
+        }));
+    }
+
+    fn effect(&mut self, op: EffectOps, args: Vec<String>, labels: Vec<String>) {
+        self.instrs.push(Code::Instruction(Instruction::Effect {
+            args,
+            funcs: vec![],
+            labels,
+            op,
+            pos: None,
+        }));
+    }
+
+    // an existing variable of the type half of the time, so that variables get reassigned
+    fn dest(&mut self, is_int: bool) -> (String, bool) {
+        let vars = if is_int { &self.ints } else { &self.bools };
+        if self.rng.chance(50) {
+            return (self.rng.pick(vars).clone(), false);
+        }
+        (self.fresh_var(), true)
+    }
+
+    fn define(&mut self, dest: String, is_new: bool, is_int: bool) {
+        if is_new {
+            if is_int {
+                self.ints.push(dest);
+            } else {
+                self.bools.push(dest);
+            }
+        }
+    }
+
+    fn statements(&mut self, depth: usize) {
+        for _ in 0..1 + self.rng.below(MAX_STATEMENTS) {
+            match self.rng.below(10) {
+                0 | 1 => self.arithmetic(),
+                2 => self.comparison(),
+                3 => {
+                    let (dest, is_new) = self.dest(true);
+                    let value = self.rng.below(20) as i64 - 5;
+                    self.constant(&dest, Literal::Int(value));
+                    self.define(dest, is_new, true);
+                }
+                4 => {
+                    let args = vec![self.rng.pick(&self.ints).clone()];
+                    self.effect(EffectOps::Print, args, vec![]);
+                }
+                5 if depth < MAX_DEPTH => self.branch(depth),
+                6 if depth < MAX_DEPTH => self.bounded_loop(depth),
+                7 => self.memory(),
+                8 if !self.callees.is_empty() => self.call(),
+                _ => self.arithmetic(),
+            }
+        }
+    }
+
+    fn arithmetic(&mut self) {
+        let arg0 = self.rng.pick(&self.ints).clone();
+        let mut arg1 = self.rng.pick(&self.ints).clone();
+        let op = self
+            .rng
+            .pick(&[ValueOps::Add, ValueOps::Sub, ValueOps::Mul, ValueOps::Div])
+            .clone();
+        if matches!(op, ValueOps::Div) {
+            // divide by a fresh nonzero constant so there is no division by zero
+            arg1 = self.fresh_var();
+            let divisor = 1 + self.rng.below(5) as i64;
+            self.constant(&arg1, Literal::Int(divisor));
+        }
+        let (dest, is_new) = self.dest(true);
+        self.value(&dest, op, vec![arg0, arg1], Type::Int);
+        self.define(dest, is_new, true);
+    }
+
+    fn comparison(&mut self) {
+        let (op, args) = if self.rng.chance(60) {
+            let op = self.rng.pick(&[
+                ValueOps::Eq,
+                ValueOps::Lt,
+                ValueOps::Gt,
+                ValueOps::Le,
+                ValueOps::Ge,
+            ]);
+            let args = vec![
+                self.rng.pick(&self.ints).clone(),
+                self.rng.pick(&self.ints).clone(),
+            ];
+            (op.clone(), args)
+        } else if self.rng.chance(30) {
+            (ValueOps::Not, vec![self.rng.pick(&self.bools).clone()])
+        } else {
+            let op = self.rng.pick(&[ValueOps::And, ValueOps::Or]);
+            let args = vec![
+                self.rng.pick(&self.bools).clone(),
+                self.rng.pick(&self.bools).clone(),
+            ];
+            (op.clone(), args)
+        };
+        let (dest, is_new) = self.dest(false);
+        self.value(&dest, op, args, Type::Bool);
+        self.define(dest, is_new, false);
+    }
+
+    // runs `body` with the current variables, and forgets the variables it introduced since
+    // they are not defined on every path afterwards
+    fn scoped(&mut self, body: impl FnOnce(&mut Self)) {
+        let (ints, bools) = (self.ints.len(), self.bools.len());
+        body(self);
+        self.ints.truncate(ints);
+        self.bools.truncate(bools);
+    }
+
+    fn branch(&mut self, depth: usize) {
+        let cond = self.rng.pick(&self.bools).clone();
+        let (then_label, else_label, end_label) =
+            (self.fresh_label(), self.fresh_label(), self.fresh_label());
+        self.effect(
+            EffectOps::Branch,
+            vec![cond],
+            vec![then_label.clone(), else_label.clone()],
+        );
+        self.label(&then_label);
+        self.scoped(|generator| generator.statements(depth + 1));
+        self.effect(EffectOps::Jump, vec![], vec![end_label.clone()]);
+        self.label(&else_label);
+        if self.rng.chance(50) {
+            self.scoped(|generator| generator.statements(depth + 1));
+        }
+        self.label(&end_label);
+    }
+
+    fn bounded_loop(&mut self, depth: usize) {
+        // the counter and bound are kept out of the variable pools so the body cannot change them
+        let (counter, bound, one, cond) = (
+            self.fresh_var(),
+            self.fresh_var(),
+            self.fresh_var(),
+            self.fresh_var(),
+        );
+        self.constant(&counter, Literal::Int(0));
+        let trips = self.rng.below(MAX_LOOP_TRIPS as usize) as i64;
+        self.constant(&bound, Literal::Int(trips));
+        self.constant(&one, Literal::Int(1));
+        let (head, body, exit) = (self.fresh_label(), self.fresh_label(), self.fresh_label());
+        self.label(&head);
+        self.value(
+            &cond,
+            ValueOps::Lt,
+            vec![counter.clone(), bound.clone()],
+            Type::Bool,
+        );
+        self.effect(
+            EffectOps::Branch,
+            vec![cond],
+            vec![body.clone(), exit.clone()],
+        );
+        self.label(&body);
+        self.scoped(|generator| generator.statements(depth + 1));
+        self.value(
+            &counter,
+            ValueOps::Add,
+            vec![counter.clone(), one],
+            Type::Int,
+        );
+        self.effect(EffectOps::Jump, vec![], vec![head]);
+        self.label(&exit);
+    }
+
+    // allocates, initializes, uses and frees an array in one straight-line stretch
+    fn memory(&mut self) {
+        let size = 1 + self.rng.below(MAX_ALLOC as usize);
+        let (count, ptr) = (self.fresh_var(), self.fresh_var());
+        let ptr_type = Type::Pointer(Box::new(Type::Int));
+        self.constant(&count, Literal::Int(size as i64));
+        self.value(&ptr, ValueOps::Alloc, vec![count], ptr_type.clone());
+        let cells: Vec<String> = (0..size)
+            .map(|offset| {
+                let (offset_var, cell) = (self.fresh_var(), self.fresh_var());
+                self.constant(&offset_var, Literal::Int(offset as i64));
+                self.value(
+                    &cell,
+                    ValueOps::PtrAdd,
+                    vec![ptr.clone(), offset_var],
+                    ptr_type.clone(),
+                );
+                let init = self.rng.pick(&self.ints).clone();
+                self.effect(EffectOps::Store, vec![cell.clone(), init], vec![]);
+                cell
+            })
+            .collect();
+        for _ in 0..self.rng.below(4) {
+            let cell = self.rng.pick(&cells).clone();
+            if self.rng.chance(50) {
+                let value = self.rng.pick(&self.ints).clone();
+                self.effect(EffectOps::Store, vec![cell, value], vec![]);
+            } else {
+                let (dest, is_new) = self.dest(true);
+                self.value(&dest, ValueOps::Load, vec![cell], Type::Int);
+                self.define(dest, is_new, true);
+            }
+        }
+        self.effect(EffectOps::Free, vec![ptr], vec![]);
+    }
+
+    fn call(&mut self) {
+        let callee = self.rng.pick(self.callees);
+        let args = callee
+            .arg_types
+            .iter()
+            .map(|arg_type| match arg_type {
+                Type::Int => self.rng.pick(&self.ints).clone(),
+                _ => self.rng.pick(&self.bools).clone(),
+            })
+            .collect();
+        match &callee.return_type {
+            Some(return_type) => {
+                let (dest, is_new) = self.dest(true);
+                self.value_call(
+                    &dest,
+                    ValueOps::Call,
+                    args,
+                    vec![callee.name.clone()],
+                    return_type.clone(),
+                );
+                self.define(dest, is_new, true);
+            }
+            None => self.instrs.push(Code::Instruction(Instruction::Effect {
+                args,
+                funcs: vec![callee.name.clone()],
+                labels: vec![],
+                op: EffectOps::Call,
+                pos: None,
+            })),
+        }
+    }
+}
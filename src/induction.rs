@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use bril_rs::{Code, Function, Instruction, Literal, ValueOps};
+
+use crate::{
+    analyze::{DataFlowAnalysis, Definition, NaturalLoop},
+    parse::{block_name_to_idx, expanded_basic_blocks, get_block_name, BasicBlock},
+};
+
+// A variable that changes linearly with a basic induction variable. At its definition in the
+// loop, the variable is `scale * base + offset`, where `base` has its current value. The basic
+// induction variable is changed by `step` at its only definition in the loop.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InductionVariable {
+    pub base: String, // the variable itself if it is a basic induction variable
+    pub scale: i64,
+    pub offset: i64,
+    pub step: i64,
+}
+
+// The definitions in and reaching a natural loop of the function
+pub struct LoopContext<'a> {
+    pub natural_loop: &'a NaturalLoop,
+    pub func: &'a Function,
+    blocks: Vec<BasicBlock>,
+    block_idx: HashMap<String, usize>,
+    reaching: &'a DataFlowAnalysis,
+    definitions: HashMap<String, Vec<Definition>>, // in the loop, by variable
+}
+
+pub fn dest(code: &Code) -> Option<&String> {
+    match code {
+        Code::Instruction(Instruction::Constant { dest, .. } | Instruction::Value { dest, .. }) => {
+            Some(dest)
+        }
+        _ => None,
+    }
+}
+
+fn int_constant(code: &Code) -> Option<i64> {
+    match code {
+        Code::Instruction(Instruction::Constant {
+            value: Literal::Int(value),
+            ..
+        }) => Some(*value),
+        _ => None,
+    }
+}
+
+// the one value of all the definitions, if they are all the same integer constant
+fn same_constant<'a>(mut defs: impl Iterator<Item = &'a Code>) -> Option<i64> {
+    let value = int_constant(defs.next()?)?;
+    defs.all(|def| int_constant(def) == Some(value))
+        .then_some(value)
+}
+
+// the comparison with its arguments swapped
+pub fn flipped(op: &ValueOps) -> Option<ValueOps> {
+    match op {
+        ValueOps::Lt => Some(ValueOps::Gt),
+        ValueOps::Le => Some(ValueOps::Ge),
+        ValueOps::Gt => Some(ValueOps::Lt),
+        ValueOps::Ge => Some(ValueOps::Le),
+        _ => None,
+    }
+}
+
+impl<'a> LoopContext<'a> {
+    pub fn new(
+        func: &'a Function,
+        natural_loop: &'a NaturalLoop,
+        reaching: &'a DataFlowAnalysis,
+    ) -> LoopContext<'a> {
+        let blocks = expanded_basic_blocks(func);
+        let mut definitions: HashMap<String, Vec<Definition>> = HashMap::new();
+        for (idx, block) in blocks.iter().enumerate() {
+            let name = get_block_name(block, idx, &func.name);
+            if !natural_loop.blocks.contains(&name) {
+                continue;
+            }
+            for (line, code) in block.iter().enumerate() {
+                if let Some(var) = dest(code) {
+                    definitions
+                        .entry(var.clone())
+                        .or_default()
+                        .push(Definition {
+                            name: var.clone(),
+                            block: name.clone(),
+                            line,
+                        });
+                }
+            }
+        }
+        LoopContext {
+            natural_loop,
+            func,
+            blocks,
+            block_idx: block_name_to_idx(func),
+            reaching,
+            definitions,
+        }
+    }
+
+    pub fn code(&self, def: &Definition) -> &Code {
+        &self.blocks[self.block_idx[&def.block]][def.line]
+    }
+
+    // the blocks of the loop with their names, in program order
+    pub fn loop_blocks(&self) -> impl Iterator<Item = (String, &BasicBlock)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(idx, block)| (get_block_name(block, idx, &self.func.name), block))
+            .filter(|(name, _)| self.natural_loop.blocks.contains(name))
+    }
+
+    pub fn block(&self, name: &String) -> &BasicBlock {
+        &self.blocks[self.block_idx[name]]
+    }
+
+    // the definitions of `var` in the loop
+    pub fn definitions(&self, var: &String) -> &[Definition] {
+        self.definitions.get(var).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // the definitions of `var` that reach the loop from outside it
+    fn entry_definitions(&self, var: &String) -> Vec<&Code> {
+        self.reaching[&self.natural_loop.header]
+            .0
+            .iter()
+            .filter(|def| &def.name == var && !self.natural_loop.blocks.contains(&def.block))
+            .map(|def| self.code(def))
+            .collect()
+    }
+
+    // reaching definitions do not know about arguments, which are never constant anyway
+    fn is_argument(&self, var: &String) -> bool {
+        self.func.args.iter().any(|arg| &arg.name == var)
+    }
+
+    // the value of `var` when the loop is entered, if it is always the same integer constant
+    pub fn entry_constant(&self, var: &String) -> Option<i64> {
+        if self.is_argument(var) {
+            return None;
+        }
+        same_constant(self.entry_definitions(var).into_iter())
+    }
+
+    // the value of `var` throughout the loop, if it is always the same integer constant
+    pub fn constant(&self, var: &String) -> Option<i64> {
+        if self.is_argument(var) {
+            return None;
+        }
+        let inside = self.definitions(var).iter().map(|def| self.code(def));
+        same_constant(self.entry_definitions(var).into_iter().chain(inside))
+    }
+}
+
+fn value_op(code: &Code) -> Option<(&ValueOps, &[String])> {
+    match code {
+        Code::Instruction(Instruction::Value { op, args, .. }) => Some((op, args.as_slice())),
+        _ => None,
+    }
+}
+
+// Finds the basic induction variables of the loop, which are only changed by adding or
+// subtracting a constant, and the variables derived from them by adding, subtracting or
+// multiplying by constants. Every induction variable has a single definition in the loop.
+pub fn induction_variables(ctx: &LoopContext) -> HashMap<String, InductionVariable> {
+    let mut single: Vec<&Definition> = ctx
+        .definitions
+        .values()
+        .filter_map(|defs| match defs.as_slice() {
+            [def] => Some(def),
+            _ => None,
+        })
+        .collect();
+    single.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut ivs: HashMap<String, InductionVariable> = HashMap::new();
+    for def in single.iter() {
+        let var = &def.name;
+        let step = match value_op(ctx.code(def)) {
+            Some((ValueOps::Add, [x, s] | [s, x])) if x == var && s != var => ctx.constant(s),
+            Some((ValueOps::Sub, [x, s])) if x == var && s != var => {
+                ctx.constant(s).map(i64::wrapping_neg)
+            }
+            _ => None,
+        };
+        if let Some(step) = step {
+            let basic = InductionVariable {
+                base: var.clone(),
+                scale: 1,
+                offset: 0,
+                step,
+            };
+            ivs.insert(var.clone(), basic);
+        }
+    }
+
+    // derived variables can be derived from each other, in any order
+    loop {
+        let mut found = false;
+        for def in single.iter() {
+            if ivs.contains_key(&def.name) {
+                continue;
+            }
+            if let Some(derived) = derived_variable(ctx, &ivs, def) {
+                ivs.insert(def.name.clone(), derived);
+                found = true;
+            }
+        }
+        if !found {
+            return ivs;
+        }
+    }
+}
+
+// Whether the derived variable `var` still has its value from the current iteration at `def`:
+// it is defined earlier in the same block, and its basic induction variable is not changed in
+// between.
+fn same_iteration(ctx: &LoopContext, var: &String, base: &String, def: &Definition) -> bool {
+    let ([var_def], [base_def]) = (ctx.definitions(var), ctx.definitions(base)) else {
+        return false;
+    };
+    let between = |other: &Definition| {
+        other.block == def.block && var_def.line < other.line && other.line < def.line
+    };
+    var_def.block == def.block && var_def.line < def.line && !between(base_def)
+}
+
+// `def` as a linear function of a basic induction variable, if it is one. The arithmetic wraps
+// around like Bril's.
+fn derived_variable(
+    ctx: &LoopContext,
+    ivs: &HashMap<String, InductionVariable>,
+    def: &Definition,
+) -> Option<InductionVariable> {
+    let (op, [a, b]) = value_op(ctx.code(def))? else {
+        return None;
+    };
+    // the induction variable, the constant and whether the induction variable comes first
+    let (iv, value, iv_first) = [(a, b, true), (b, a, false)]
+        .into_iter()
+        .find_map(|(x, c, first)| Some((ivs.get(x)?, ctx.constant(c)?, first, x)))
+        .and_then(|(iv, value, first, x)| {
+            (&iv.base == x || same_iteration(ctx, x, &iv.base, def)).then_some((iv, value, first))
+        })?;
+    let (scale, offset) = match op {
+        ValueOps::Add => (iv.scale, iv.offset.wrapping_add(value)),
+        ValueOps::Sub if iv_first => (iv.scale, iv.offset.wrapping_sub(value)),
+        ValueOps::Sub => (iv.scale.wrapping_neg(), value.wrapping_sub(iv.offset)),
+        ValueOps::Mul => (iv.scale.wrapping_mul(value), iv.offset.wrapping_mul(value)),
+        _ => return None,
+    };
+    Some(InductionVariable {
+        base: iv.base.clone(),
+        scale,
+        offset,
+        step: iv.step,
+    })
+}
@@ -0,0 +1,271 @@
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Program, ValueOps};
+
+use crate::{
+    analysis_manager::AnalysisManager,
+    callgraph::{call_graph, callees, recursive_functions},
+    error::{Error, Result},
+    pass::Pass,
+};
+
+// callees with more instructions than this are left alone by the `inline` pass
+pub const DEFAULT_INLINE_THRESHOLD: usize = 20;
+
+pub struct Inliner {
+    threshold: usize,
+}
+
+impl Inliner {
+    pub fn new(threshold: usize) -> Inliner {
+        Inliner { threshold }
+    }
+}
+
+impl Pass for Inliner {
+    fn name(&self) -> &str {
+        "inline"
+    }
+
+    fn run_on_program(&self, prog: &Program, _analyses: &mut AnalysisManager) -> Result<Program> {
+        inline_calls(prog, self.threshold)
+    }
+}
+
+// number of instructions, not counting labels
+pub fn size(func: &Function) -> usize {
+    func.instrs
+        .iter()
+        .filter(|code| matches!(code, Code::Instruction(_)))
+        .count()
+}
+
+// every variable and label name that appears in the function
+pub fn names(func: &Function) -> HashSet<String> {
+    let mut names: HashSet<String> = func.args.iter().map(|arg| arg.name.clone()).collect();
+    for code in func.instrs.iter() {
+        match code {
+            Code::Label { label, .. } => {
+                names.insert(label.clone());
+            }
+            Code::Instruction(Instruction::Constant { dest, .. }) => {
+                names.insert(dest.clone());
+            }
+            Code::Instruction(Instruction::Value {
+                args, dest, labels, ..
+            }) => {
+                names.insert(dest.clone());
+                names.extend(args.iter().chain(labels.iter()).cloned());
+            }
+            Code::Instruction(Instruction::Effect { args, labels, .. }) => {
+                names.extend(args.iter().chain(labels.iter()).cloned());
+            }
+        }
+    }
+    names
+}
+
+// `name`, or `name` with underscores appended if it is taken
+pub fn fresh_name(mut name: String, taken: &mut HashSet<String>) -> String {
+    while taken.contains(&name) {
+        name = name + "_";
+    }
+    taken.insert(name.clone());
+    name
+}
+
+// Inlines every call to a function that is not recursive and has at most `threshold`
+// instructions. Calls in the inlined code are inlined in turn.
+pub fn inline_calls(prog: &Program, threshold: usize) -> Result<Program> {
+    let recursive = recursive_functions(&call_graph(prog));
+    let inlinable: HashMap<&String, &Function> = prog
+        .functions
+        .iter()
+        .filter(|func| !recursive.contains(&func.name) && size(func) <= threshold)
+        .map(|func| (&func.name, func))
+        .collect();
+    let mut new_prog = prog.clone();
+    for func in new_prog.functions.iter_mut() {
+        let mut taken = names(func);
+        // inlinable functions only call each other acyclically, so this runs out of calls
+        while let Some((idx, callee)) = func.instrs.iter().enumerate().find_map(|(idx, code)| {
+            let callee = inlinable.get(callees(code).first()?)?;
+            Some((idx, *callee))
+        }) {
+            let body = inline_call(&func.name, &func.instrs[idx], callee, &mut taken)?;
+            let block = func.instrs[..idx].iter().rev().find_map(label_of).cloned();
+            let continuation = body.iter().rev().find_map(label_of).cloned();
+            func.instrs.splice(idx..idx + 1, body);
+            // the code after the call now ends the last block of the inlined body
+            if let (Some(block), Some(continuation)) = (block, continuation) {
+                retarget_phis(&mut func.instrs, &block, &continuation);
+            }
+        }
+    }
+    Ok(new_prog)
+}
+
+fn label_of(code: &Code) -> Option<&String> {
+    match code {
+        Code::Label { label, .. } => Some(label),
+        Code::Instruction(_) => None,
+    }
+}
+
+// Makes phis that take a value from block `from` take it from block `to` instead.
+fn retarget_phis(instrs: &mut [Code], from: &String, to: &String) {
+    for code in instrs.iter_mut() {
+        if let Code::Instruction(Instruction::Value {
+            labels,
+            op: ValueOps::Phi,
+            ..
+        }) = code
+        {
+            for label in labels.iter_mut().filter(|label| *label == from) {
+                *label = to.clone();
+            }
+        }
+    }
+}
+
+// Replaces the call with a copy of the callee's body. The callee's variables and labels are
+// renamed apart from the caller's, its arguments are copied into its parameters, and each
+// `ret` copies the returned value into the call's destination and jumps past the body. Fails
+// if the call in `caller` has the wrong number of arguments or uses the result of a void
+// function.
+fn inline_call(
+    caller: &String,
+    call: &Code,
+    callee: &Function,
+    taken: &mut HashSet<String>,
+) -> Result<Vec<Code>> {
+    let (dest, args, pos) = match call {
+        Code::Instruction(Instruction::Value {
+            dest, args, pos, ..
+        }) => (Some(dest), args, pos),
+        Code::Instruction(Instruction::Effect { args, pos, .. }) => (None, args, pos),
+        _ => {
+            return Err(Error::Malformed {
+                func: caller.clone(),
+                msg: format!(
+                    "cannot inline @{} into something other than a call",
+                    callee.name
+                ),
+                pos: None,
+            })
+        }
+    };
+    if args.len() != callee.args.len() {
+        return Err(Error::Malformed {
+            func: caller.clone(),
+            msg: format!(
+                "@{} takes {} arguments, got {}",
+                callee.name,
+                callee.args.len(),
+                args.len()
+            ),
+            pos: pos.clone(),
+        });
+    }
+    // the call's destination and its type
+    let result = match (dest, &callee.return_type) {
+        (Some(dest), Some(return_type)) => Some((dest, return_type)),
+        (Some(_), None) => {
+            return Err(Error::Malformed {
+                func: caller.clone(),
+                msg: format!(
+                    "call uses the result of @{}, which returns nothing",
+                    callee.name
+                ),
+                pos: pos.clone(),
+            })
+        }
+        (None, _) => None,
+    };
+    // variables and labels live in different namespaces, but sharing one map is still correct
+    let mut renamed: HashMap<String, String> = HashMap::new();
+    let mut rename = |name: &String, taken: &mut HashSet<String>| -> String {
+        renamed
+            .entry(name.clone())
+            .or_insert_with(|| fresh_name(format!("{}.{}", callee.name, name), taken))
+            .clone()
+    };
+    let return_label = fresh_name(format!("{}.return", callee.name), taken);
+
+    let mut body: Vec<Code> = callee
+        .args
+        .iter()
+        .zip(args.iter())
+        .map(|(param, arg)| {
+            Code::Instruction(Instruction::Value {
+                args: vec![arg.clone()],
+                dest: rename(&param.name, taken),
+                funcs: vec![],
+                labels: vec![],
+                op: ValueOps::Id,
+                pos: pos.clone(),
+                op_type: param.arg_type.clone(),
+            })
+        })
+        .collect();
+    let mut jumps_to_return = false;
+    for (i, code) in callee.instrs.iter().enumerate() {
+        if let Code::Instruction(Instruction::Effect {
+            op: EffectOps::Return,
+            args: ret_args,
+            pos: ret_pos,
+            ..
+        }) = code
+        {
+            if let (Some((dest, return_type)), Some(value)) = (result, ret_args.first()) {
+                body.push(Code::Instruction(Instruction::Value {
+                    args: vec![rename(value, taken)],
+                    dest: dest.clone(),
+                    funcs: vec![],
+                    labels: vec![],
+                    op: ValueOps::Id,
+                    pos: ret_pos.clone(),
+                    op_type: return_type.clone(),
+                }));
+            }
+            // the last `ret` can just fall through to the code after the call
+            if i + 1 < callee.instrs.len() {
+                jumps_to_return = true;
+                body.push(Code::Instruction(Instruction::Effect {
+                    args: vec![],
+                    funcs: vec![],
+                    labels: vec![return_label.clone()],
+                    op: EffectOps::Jump,
+                    pos: ret_pos.clone(),
+                }));
+            }
+            continue;
+        }
+        let mut code = code.clone();
+        match &mut code {
+            Code::Label { label, .. } => *label = rename(label, taken),
+            Code::Instruction(Instruction::Constant { dest, .. }) => *dest = rename(dest, taken),
+            Code::Instruction(Instruction::Value {
+                args, dest, labels, ..
+            }) => {
+                *dest = rename(dest, taken);
+                for name in args.iter_mut().chain(labels.iter_mut()) {
+                    *name = rename(name, taken);
+                }
+            }
+            Code::Instruction(Instruction::Effect { args, labels, .. }) => {
+                for name in args.iter_mut().chain(labels.iter_mut()) {
+                    *name = rename(name, taken);
+                }
+            }
+        }
+        body.push(code);
+    }
+    if jumps_to_return {
+        body.push(Code::Label {
+            label: return_label,
+            pos: pos.clone(),
+        });
+    }
+    Ok(body)
+}
@@ -0,0 +1,448 @@
+use std::{collections::HashMap, fmt};
+
+use bril_rs::{Argument, Code, EffectOps, Function, Instruction, Literal, Program, Type, ValueOps};
+
+use crate::{
+    error::{Error, Result},
+    util::instruction_pos,
+    verify::verify_program,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Pointer(Pointer),
+}
+
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+pub struct Pointer {
+    alloc: usize,
+    offset: i64,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Pointer(ptr) => write!(f, "ptr({}+{})", ptr.alloc, ptr.offset),
+        }
+    }
+}
+
+// what a program did when run, until it finished or failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Execution {
+    pub output: Vec<String>, // one line per `print`, up to the failure if there was one
+    pub return_value: Option<Value>,
+    pub instructions: u64, // dynamic instruction count, labels excluded
+    pub failure: Option<Error>,
+    pub gave_up: bool, // the failure is running out of instructions
+}
+
+// Runs `main` with `args` parsed according to its parameter types. The program is verified
+// first, and fails to run if it is malformed or the arguments do not fit. Errors while running
+// end the execution with a failure instead. Like brili, reading uninitialized memory and ending
+// with unfreed memory are errors. Memory is not rolled back when a guard fails.
+pub fn interpret(prog: &Program, args: &[String]) -> Result<Execution> {
+    verify_program(prog)?;
+    Interpreter::new(prog, None).run(args)
+}
+
+// same as `interpret`, but gives up after executing `max_instructions` instructions
+pub fn interpret_limited(
+    prog: &Program,
+    args: &[String],
+    max_instructions: u64,
+) -> Result<Execution> {
+    verify_program(prog)?;
+    Interpreter::new(prog, Some(max_instructions)).run(args)
+}
+
+// calls nested deeper than this are an error rather than overflowing the stack
+const MAX_CALL_DEPTH: usize = 100_000;
+// stack reserved for each nested call, the interpreter runs on a thread with room for all of them
+const STACK_PER_CALL: usize = 16 * 1024;
+
+struct Interpreter<'a> {
+    functions: HashMap<&'a String, &'a Function>,
+    heap: HashMap<usize, Vec<Option<Value>>>, // allocation id to its cells
+    next_alloc: usize,
+    output: Vec<String>,
+    instructions: u64,
+    max_instructions: Option<u64>,
+    depth: usize, // calls currently running
+}
+
+fn parse_arg(arg: &Argument, text: &String) -> Option<Value> {
+    match arg.arg_type {
+        Type::Int => text.parse().ok().map(Value::Int),
+        Type::Bool => text.parse().ok().map(Value::Bool),
+        _ => None,
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(prog: &'a Program, max_instructions: Option<u64>) -> Interpreter<'a> {
+        Interpreter {
+            functions: prog
+                .functions
+                .iter()
+                .map(|func| (&func.name, func))
+                .collect(),
+            heap: HashMap::new(),
+            next_alloc: 0,
+            output: vec![],
+            instructions: 0,
+            max_instructions,
+            depth: 0,
+        }
+    }
+
+    fn run(mut self, args: &[String]) -> Result<Execution> {
+        let main_error = |msg: String| Error::Runtime {
+            func: String::from("main"),
+            msg,
+            pos: None,
+        };
+        let main = *self
+            .functions
+            .get(&String::from("main"))
+            .ok_or_else(|| main_error(String::from("no main function")))?;
+        if main.args.len() != args.len() {
+            return Err(main_error(format!(
+                "expected {} arguments, got {}",
+                main.args.len(),
+                args.len()
+            )));
+        }
+        let values = main
+            .args
+            .iter()
+            .zip(args)
+            .map(|(arg, text)| {
+                parse_arg(arg, text).ok_or_else(|| {
+                    main_error(format!(
+                        "cannot pass '{}' as {}: {}",
+                        text, arg.name, arg.arg_type
+                    ))
+                })
+            })
+            .collect::<Result<Vec<Value>>>()?;
+
+        let result = std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .stack_size(MAX_CALL_DEPTH * STACK_PER_CALL)
+                .spawn_scoped(scope, || self.call(main, values, &main_error))
+                .map_err(|err| main_error(format!("cannot start the interpreter: {}", err)))?
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        });
+        let (return_value, failure) = match result {
+            Ok(_) if !self.heap.is_empty() => (
+                None,
+                Some(main_error(String::from(
+                    "some memory locations have not been freed by end of execution",
+                ))),
+            ),
+            Ok(return_value) => (return_value, None),
+            Err(err) => (None, Some(err)),
+        };
+        Ok(Execution {
+            output: self.output,
+            return_value,
+            instructions: self.instructions,
+            failure,
+            gave_up: self
+                .max_instructions
+                .is_some_and(|max| self.instructions > max),
+        })
+    }
+
+    // `error` reports errors at the call
+    fn call(
+        &mut self,
+        func: &'a Function,
+        args: Vec<Value>,
+        error: &impl Fn(String) -> Error,
+    ) -> Result<Option<Value>> {
+        if args.len() != func.args.len() {
+            return Err(error(format!(
+                "@{} takes {} arguments, got {}",
+                func.name,
+                func.args.len(),
+                args.len()
+            )));
+        }
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(error(format!("more than {} nested calls", MAX_CALL_DEPTH)));
+        }
+        self.depth += 1;
+        let result = self.execute(func, args);
+        self.depth -= 1;
+        result
+    }
+
+    fn execute(&mut self, func: &'a Function, args: Vec<Value>) -> Result<Option<Value>> {
+        let labels: HashMap<&String, usize> = func
+            .instrs
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, code)| match code {
+                Code::Label { label, .. } => Some((label, idx)),
+                Code::Instruction(_) => None,
+            })
+            .collect();
+        let mut env: HashMap<String, Value> = func
+            .args
+            .iter()
+            .map(|arg| arg.name.clone())
+            .zip(args)
+            .collect();
+        // phis choose their argument by the label executed before the current one
+        let mut last_label: Option<&String> = None;
+        let mut current_label: Option<&String> = None;
+        // environments to restore when a guard fails
+        let mut speculating: Vec<HashMap<String, Value>> = vec![];
+
+        let mut pc = 0;
+        while pc < func.instrs.len() {
+            let instr = match &func.instrs[pc] {
+                Code::Label { label, .. } => {
+                    last_label = current_label;
+                    current_label = Some(label);
+                    pc += 1;
+                    continue;
+                }
+                Code::Instruction(instr) => instr,
+            };
+            pc += 1;
+
+            let error = |msg: String| Error::Runtime {
+                func: func.name.clone(),
+                msg,
+                pos: instruction_pos(instr),
+            };
+            self.instructions += 1;
+            if let Some(max) = self.max_instructions {
+                if self.instructions > max {
+                    return Err(error(format!("gave up after {} instructions", max)));
+                }
+            }
+            let get = |var: &String| -> Result<Value> {
+                env.get(var)
+                    .copied()
+                    .ok_or_else(|| error(format!("undefined variable {}", var)))
+            };
+            let jump = |label: &String| -> Result<usize> {
+                labels
+                    .get(label)
+                    .copied()
+                    .ok_or_else(|| error(format!("undefined label .{}", label)))
+            };
+
+            match instr {
+                Instruction::Constant { dest, value, .. } => {
+                    let value = match value {
+                        Literal::Int(n) => Value::Int(*n),
+                        Literal::Bool(b) => Value::Bool(*b),
+                    };
+                    env.insert(dest.clone(), value);
+                }
+                Instruction::Value {
+                    args,
+                    dest,
+                    funcs,
+                    labels: phi_labels,
+                    op,
+                    ..
+                } => {
+                    let value = match op {
+                        ValueOps::Phi => {
+                            // the variable becomes undefined if no label matches
+                            let chosen = phi_labels
+                                .iter()
+                                .position(|label| Some(label) == last_label)
+                                .and_then(|idx| env.get(&args[idx]).copied());
+                            let Some(value) = chosen else {
+                                env.remove(dest);
+                                continue;
+                            };
+                            value
+                        }
+                        ValueOps::Call => {
+                            let values = args.iter().map(get).collect::<Result<Vec<Value>>>()?;
+                            let callee = self.callee(&funcs[0], &error)?;
+                            self.call(callee, values, &error)?.ok_or_else(|| {
+                                error(format!("@{} did not return a value", funcs[0]))
+                            })?
+                        }
+                        ValueOps::Id => get(&args[0])?,
+                        ValueOps::Not => match get(&args[0])? {
+                            Value::Bool(b) => Value::Bool(!b),
+                            _ => return Err(error(String::from("not needs a bool"))),
+                        },
+                        ValueOps::Alloc => match get(&args[0])? {
+                            Value::Int(size) if size > 0 => {
+                                let alloc = self.next_alloc;
+                                self.next_alloc += 1;
+                                self.heap.insert(alloc, vec![None; size as usize]);
+                                Value::Pointer(Pointer { alloc, offset: 0 })
+                            }
+                            _ => return Err(error(String::from("alloc needs a positive size"))),
+                        },
+                        ValueOps::Load => {
+                            let ptr = self.pointer(get(&args[0])?, &error)?;
+                            self.heap[&ptr.alloc][ptr.offset as usize].ok_or_else(|| {
+                                error(format!("load from uninitialized memory {}", args[0]))
+                            })?
+                        }
+                        ValueOps::PtrAdd => match (get(&args[0])?, get(&args[1])?) {
+                            (Value::Pointer(ptr), Value::Int(n)) => Value::Pointer(Pointer {
+                                alloc: ptr.alloc,
+                                offset: ptr.offset.wrapping_add(n),
+                            }),
+                            _ => {
+                                return Err(error(String::from(
+                                    "ptradd needs a pointer and an int",
+                                )))
+                            }
+                        },
+                        op => Self::binary_op(op, get(&args[0])?, get(&args[1])?, &error)?,
+                    };
+                    env.insert(dest.clone(), value);
+                }
+                Instruction::Effect {
+                    args,
+                    funcs,
+                    labels: targets,
+                    op,
+                    ..
+                } => match op {
+                    EffectOps::Jump => pc = jump(&targets[0])?,
+                    EffectOps::Branch => match get(&args[0])? {
+                        Value::Bool(cond) => {
+                            pc = jump(&targets[if cond { 0 } else { 1 }])?;
+                        }
+                        _ => return Err(error(String::from("br needs a bool"))),
+                    },
+                    EffectOps::Call => {
+                        let values = args.iter().map(get).collect::<Result<Vec<Value>>>()?;
+                        let callee = self.callee(&funcs[0], &error)?;
+                        self.call(callee, values, &error)?;
+                    }
+                    EffectOps::Return => {
+                        if !speculating.is_empty() {
+                            return Err(error(String::from("ret while speculating")));
+                        }
+                        return args.first().map(get).transpose();
+                    }
+                    EffectOps::Print => {
+                        let values = args.iter().map(get).collect::<Result<Vec<Value>>>()?;
+                        self.output.push(
+                            values
+                                .iter()
+                                .map(|value| value.to_string())
+                                .collect::<Vec<String>>()
+                                .join(" "),
+                        );
+                    }
+                    EffectOps::Nop => {}
+                    EffectOps::Store => {
+                        let ptr = self.pointer(get(&args[0])?, &error)?;
+                        let value = get(&args[1])?;
+                        self.heap.get_mut(&ptr.alloc).unwrap()[ptr.offset as usize] = Some(value);
+                    }
+                    EffectOps::Free => {
+                        let ptr = self.pointer(get(&args[0])?, &error)?;
+                        if ptr.offset != 0 {
+                            return Err(error(format!(
+                                "free of {} which is not the start of an allocation",
+                                args[0]
+                            )));
+                        }
+                        self.heap.remove(&ptr.alloc);
+                    }
+                    EffectOps::Speculate => speculating.push(env.clone()),
+                    EffectOps::Commit => {
+                        if speculating.pop().is_none() {
+                            return Err(error(String::from("commit while not speculating")));
+                        }
+                    }
+                    EffectOps::Guard => match get(&args[0])? {
+                        Value::Bool(true) => {}
+                        Value::Bool(false) => {
+                            pc = jump(&targets[0])?;
+                            env = speculating.pop().ok_or_else(|| {
+                                error(String::from("guard while not speculating"))
+                            })?;
+                        }
+                        _ => return Err(error(String::from("guard needs a bool"))),
+                    },
+                },
+            }
+        }
+
+        if !speculating.is_empty() {
+            return Err(Error::Runtime {
+                func: func.name.clone(),
+                msg: String::from("function ended while speculating"),
+                pos: None,
+            });
+        }
+        Ok(None)
+    }
+
+    fn callee(&self, name: &String, error: &impl Fn(String) -> Error) -> Result<&'a Function> {
+        self.functions
+            .get(name)
+            .copied()
+            .ok_or_else(|| error(format!("call to undefined function @{}", name)))
+    }
+
+    // checks that `value` points into a live allocation
+    fn pointer(&self, value: Value, error: &impl Fn(String) -> Error) -> Result<Pointer> {
+        let Value::Pointer(ptr) = value else {
+            return Err(error(String::from("expected a pointer")));
+        };
+        match self.heap.get(&ptr.alloc) {
+            None => Err(error(String::from("access to freed memory"))),
+            Some(cells) if ptr.offset < 0 || ptr.offset as usize >= cells.len() => Err(error(
+                format!("access out of bounds at offset {}", ptr.offset),
+            )),
+            Some(_) => Ok(ptr),
+        }
+    }
+
+    fn binary_op(
+        op: &ValueOps,
+        arg0: Value,
+        arg1: Value,
+        error: &impl Fn(String) -> Error,
+    ) -> Result<Value> {
+        let value = match (op, arg0, arg1) {
+            (ValueOps::Add, Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_add(b)),
+            (ValueOps::Sub, Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_sub(b)),
+            (ValueOps::Mul, Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_mul(b)),
+            (ValueOps::Div, Value::Int(_), Value::Int(0)) => {
+                return Err(error(String::from("division by zero")))
+            }
+            (ValueOps::Div, Value::Int(a), Value::Int(b)) => Value::Int(a.wrapping_div(b)),
+            (ValueOps::Eq, Value::Int(a), Value::Int(b)) => Value::Bool(a == b),
+            (ValueOps::Lt, Value::Int(a), Value::Int(b)) => Value::Bool(a < b),
+            (ValueOps::Gt, Value::Int(a), Value::Int(b)) => Value::Bool(a > b),
+            (ValueOps::Le, Value::Int(a), Value::Int(b)) => Value::Bool(a <= b),
+            (ValueOps::Ge, Value::Int(a), Value::Int(b)) => Value::Bool(a >= b),
+            (ValueOps::And, Value::Bool(a), Value::Bool(b)) => Value::Bool(a && b),
+            (ValueOps::Or, Value::Bool(a), Value::Bool(b)) => Value::Bool(a || b),
+            (op, arg0, arg1) => {
+                return Err(error(format!(
+                    "cannot apply {} to {} and {}",
+                    op, arg0, arg1
+                )))
+            }
+        };
+        Ok(value)
+    }
+}
@@ -1,6 +1,28 @@
+pub mod alias;
+pub mod analysis_manager;
 pub mod analyze;
+pub mod callgraph;
+pub mod check;
+pub mod dfe;
+pub mod error;
+pub mod fuzz;
+pub mod generate;
+pub mod induction;
+pub mod inline;
+pub mod interp;
+pub mod link;
 pub mod lvn;
+pub mod mem2reg;
+pub mod memcheck;
 pub mod optimize;
 pub mod parse;
+pub mod pass;
+pub mod reduce;
+pub mod specialize;
 pub mod ssa;
+pub mod strength;
+pub mod summary;
+pub mod tce;
+pub mod unroll;
 pub mod util;
+pub mod verify;
@@ -0,0 +1,274 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsStr,
+    fs::File,
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+use bril_rs::{Code, Import, ImportedFunction, Instruction, Program};
+
+use crate::{
+    callgraph::callees,
+    error::{Error, Result},
+};
+
+// a file of the linked program
+struct Module {
+    path: PathBuf, // as given for the root, canonical for imported files
+    imports: Vec<Import>,
+    aliases: HashMap<String, String>, // alias -> name of the imported function
+}
+
+// A program and everything it imports, with the functions of all files in one namespace.
+// Remembers which file each function came from, so the program can be split up again.
+pub struct LinkedProgram {
+    pub prog: Program,
+    modules: Vec<Module>,
+    owners: HashMap<String, usize>, // function name -> index of its module
+}
+
+// `path` relative to the importing file's directory, or else to the first directory of the
+// search path that has it
+fn resolve(path: &Path, importer_dir: &Path, search_path: &[PathBuf]) -> Result<PathBuf> {
+    std::iter::once(importer_dir)
+        .chain(search_path.iter().map(PathBuf::as_path))
+        .map(|dir| dir.join(path))
+        .find(|candidate| candidate.is_file())
+        .and_then(|found| found.canonicalize().ok())
+        .ok_or_else(|| {
+            Error::Link(format!(
+                "cannot find imported file {} in {} or the search path",
+                path.display(),
+                importer_dir.display()
+            ))
+        })
+}
+
+// Reads an imported file, which is JSON unless its extension is `.bril`. Text files are turned into
+// JSON by `bril2json`.
+fn load_file(file: &Path) -> Result<Program> {
+    let cannot_open =
+        |err: std::io::Error| Error::Link(format!("cannot open {}: {}", file.display(), err));
+    let json = if file.extension() == Some(OsStr::new("bril")) {
+        let output = Command::new("bril2json")
+            .stdin(File::open(file).map_err(cannot_open)?)
+            .output()
+            .map_err(|err| Error::Link(format!("cannot run bril2json: {}", err)))?;
+        if !output.status.success() {
+            return Err(Error::Link(format!(
+                "bril2json cannot parse {}: {}",
+                file.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        output.stdout
+    } else {
+        std::fs::read(file).map_err(cannot_open)?
+    };
+    serde_json::from_slice(&json)
+        .map_err(|err| Error::Link(format!("{} is not a Bril program: {}", file.display(), err)))
+}
+
+// `to` relative to the directory `from`, where both are relative to the same directory
+fn relative_to(to: &Path, from: &Path) -> PathBuf {
+    let common = from
+        .components()
+        .zip(to.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    from.components()
+        .skip(common)
+        .map(|_| Component::ParentDir)
+        .chain(to.components().skip(common))
+        .collect()
+}
+
+fn rename_calls(instrs: &mut [Code], renamed: &HashMap<String, String>) {
+    for code in instrs.iter_mut() {
+        if let Code::Instruction(
+            Instruction::Value { funcs, .. } | Instruction::Effect { funcs, .. },
+        ) = code
+        {
+            for func in funcs.iter_mut() {
+                if let Some(name) = renamed.get(func) {
+                    *func = name.clone();
+                }
+            }
+        }
+    }
+}
+
+// Loads the files `root` imports, and the files they import, looking for them relative to the
+// importing file and then in `search_path`. Calls through aliases are redirected to the
+// function's real name, which has to be unique across all the files.
+pub fn link(root: Program, root_path: &Path, search_path: &[PathBuf]) -> Result<LinkedProgram> {
+    let mut linked = LinkedProgram {
+        prog: Program {
+            functions: vec![],
+            imports: vec![],
+        },
+        modules: vec![],
+        owners: HashMap::new(),
+    };
+    let mut loaded: HashMap<PathBuf, usize> = HashMap::new();
+    // files importing the root get the root itself, not a second copy of it
+    if let Ok(root_file) = root_path.canonicalize() {
+        loaded.insert(root_file, 0);
+    }
+    let mut queue: VecDeque<(PathBuf, Program)> = VecDeque::from([(root_path.to_path_buf(), root)]);
+    while let Some((path, prog)) = queue.pop_front() {
+        let idx = linked.modules.len();
+        // the root read from stdin, `-`, imports relative to the current directory
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let mut aliases = HashMap::new();
+        for import in prog.imports.iter() {
+            let file = resolve(&import.path, &dir, search_path)?;
+            if !loaded.contains_key(&file) {
+                let imported = load_file(&file)?;
+                // reserve the index of the module, it is added once it comes out of the queue
+                loaded.insert(file.clone(), idx + 1 + queue.len());
+                queue.push_back((file, imported));
+            }
+            for func in import.functions.iter() {
+                if let Some(alias) = &func.alias {
+                    aliases.insert(alias.clone(), func.name.clone());
+                }
+            }
+        }
+        for mut func in prog.functions {
+            if let Some(&owner) = linked.owners.get(&func.name) {
+                return Err(Error::Link(format!(
+                    "function @{} is defined in both {} and {}",
+                    func.name,
+                    linked.modules[owner].path.display(),
+                    path.display()
+                )));
+            }
+            rename_calls(&mut func.instrs, &aliases);
+            linked.owners.insert(func.name.clone(), idx);
+            linked.prog.functions.push(func);
+        }
+        linked.modules.push(Module {
+            path,
+            imports: prog.imports,
+            aliases,
+        });
+    }
+
+    // only now are all the functions known
+    for module in linked.modules.iter() {
+        let dir = module.path.parent().unwrap_or(Path::new("."));
+        for import in module.imports.iter() {
+            let file = resolve(&import.path, dir, search_path)?;
+            for func in import.functions.iter() {
+                if linked.owners.get(&func.name) != loaded.get(&file) {
+                    return Err(Error::Link(format!(
+                        "{} imports @{} from {}, which does not define it",
+                        module.path.display(),
+                        func.name,
+                        file.display()
+                    )));
+                }
+            }
+        }
+    }
+    Ok(linked)
+}
+
+impl LinkedProgram {
+    // Where each file goes when the program is written out, relative to the directory it is
+    // written to and with the extension `extension`. The root keeps its file name, or is `stdin`,
+    // and imported files keep their place relative to it. Imported files from outside the root's
+    // directory go next to it.
+    fn output_paths(&self, extension: &str) -> Vec<PathBuf> {
+        let root = &self.modules[0].path;
+        let root_dir = match root.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        }
+        .canonicalize()
+        .ok();
+        let mut paths: Vec<PathBuf> = vec![];
+        for (idx, module) in self.modules.iter().enumerate() {
+            let relative = root_dir
+                .as_ref()
+                .and_then(|dir| module.path.strip_prefix(dir).ok());
+            let mut path = match (idx, relative) {
+                (0, _) if root == Path::new("-") => PathBuf::from("stdin"),
+                (0, _) | (_, None) => PathBuf::from(module.path.file_name().unwrap_or_default()),
+                (_, Some(relative)) => relative.to_path_buf(),
+            }
+            .with_extension(extension);
+            while paths.contains(&path) {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy() + "_";
+                path = path
+                    .with_file_name(stem.into_owned())
+                    .with_extension(extension);
+            }
+            paths.push(path);
+        }
+        paths
+    }
+
+    // Splits a transformed version of the linked program back into its files, each with the path
+    // it is written to relative to the output directory and extension `extension`. Functions the
+    // transformation added go to the root file. Every file imports the functions from other
+    // files it now calls, under their original aliases and from the path the other file is
+    // written to.
+    pub fn split(&self, prog: &Program, extension: &str) -> Vec<(PathBuf, Program)> {
+        let owner = |name: &String| self.owners.get(name).copied().unwrap_or(0);
+        let paths = self.output_paths(extension);
+        self.modules
+            .iter()
+            .enumerate()
+            .map(|(idx, module)| {
+                let mut functions: Vec<_> = prog
+                    .functions
+                    .iter()
+                    .filter(|func| owner(&func.name) == idx)
+                    .cloned()
+                    .collect();
+                let defined: HashSet<&String> = functions.iter().map(|func| &func.name).collect();
+                let mut called: Vec<String> = vec![];
+                for callee in functions
+                    .iter()
+                    .flat_map(|func| func.instrs.iter().flat_map(callees))
+                {
+                    if !defined.contains(callee) && !called.contains(callee) {
+                        called.push(callee.clone());
+                    }
+                }
+
+                let unaliased: HashMap<String, String> = module
+                    .aliases
+                    .iter()
+                    .map(|(alias, name)| (name.clone(), alias.clone()))
+                    .collect();
+                let mut imports: Vec<Import> = vec![];
+                let dir = paths[idx].parent().unwrap_or(Path::new(""));
+                for callee in called.iter() {
+                    let path = relative_to(&paths[owner(callee)], dir);
+                    let func = ImportedFunction {
+                        alias: unaliased.get(callee).cloned(),
+                        name: callee.clone(),
+                    };
+                    match imports.iter_mut().find(|import| import.path == path) {
+                        Some(import) => import.functions.push(func),
+                        None => imports.push(Import {
+                            functions: vec![func],
+                            path,
+                        }),
+                    }
+                }
+                for func in functions.iter_mut() {
+                    rename_calls(&mut func.instrs, &unaliased);
+                }
+                (paths[idx].clone(), Program { functions, imports })
+            })
+            .collect()
+    }
+}
@@ -1,35 +1,57 @@
 use std::collections::{HashMap, HashSet};
 
-use bril_rs::{Code, ConstOps, Instruction, Literal, Type, ValueOps};
+use bril_rs::{Code, ConstOps, EffectOps, Instruction, Literal, Position, Type, ValueOps};
 
+use crate::error::{Error, Result};
 use crate::parse::BasicBlock;
+use crate::summary::{is_pure_call, Summaries};
+use crate::util::instruction_pos;
+use crate::verify::{effect_arity, value_arity};
 
 #[derive(Eq, PartialEq, Hash, Debug)]
 pub enum LVNValue {
     Constant(Literal),
     ValueBinaryOp(ValueOps, usize, usize),
     ValueUnaryOp(ValueOps, usize),
+    PureCall(String, Vec<usize>), // callee and value numbers of the arguments
 }
 
-pub struct LVN {
+// what is known about a pointer that was derived from an `alloc` in the current block
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct PointerInfo {
+    base: usize,         // value number of the allocation
+    offset: Option<i64>, // None if the offset from the base is not a known constant
+}
+
+pub struct LVN<'a> {
+    func: &'a String, // name of the function the block is from, for errors
     next: usize,
     folding: bool,
+    summaries: &'a Summaries, // calls to pure functions are numbered like other values
     var2num: HashMap<String, usize>, // the value of variables, multiple variables can have same value
     val2num: HashMap<LVNValue, usize>,
     num2var: HashMap<usize, String>,
     num2const: HashMap<usize, Literal>,
+    num2ptr: HashMap<usize, PointerInfo>,
+    escaped: HashSet<usize>, // allocations that may be reachable through other pointers
+    memory: HashMap<usize, usize>, // pointer value number -> value number stored at it
 }
 
-impl LVN {
-    pub fn new(folding: bool) -> LVN {
+impl<'a> LVN<'a> {
+    pub fn new(func: &'a String, folding: bool, summaries: &'a Summaries) -> LVN<'a> {
         // LVN table: Number | Value | Variable
         LVN {
+            func,
             next: 0,
             folding,
+            summaries,
             val2num: HashMap::new(),
             num2var: HashMap::new(),
             var2num: HashMap::new(),
             num2const: HashMap::new(),
+            num2ptr: HashMap::new(),
+            escaped: HashSet::new(),
+            memory: HashMap::new(),
         }
     }
 
@@ -71,12 +93,16 @@ impl LVN {
         let mut read: HashSet<String> = HashSet::new();
         let mut written: HashSet<String> = HashSet::new();
         for instr in block {
-            if let Code::Instruction(Instruction::Value { args, dest, .. }) = instr {
+            if let Code::Instruction(Instruction::Value { args, .. })
+            | Code::Instruction(Instruction::Effect { args, .. }) = instr
+            {
                 read.extend(
                     args.clone()
                         .into_iter()
                         .filter(|arg| !written.contains(arg)),
                 );
+            }
+            if let Code::Instruction(Instruction::Value { dest, .. }) = instr {
                 written.insert(dest.clone());
             }
             if let Code::Instruction(Instruction::Constant { dest, .. }) = instr {
@@ -111,17 +137,52 @@ impl LVN {
         (self.register_dest(&dest, val_num, last_write), val_num)
     }
 
+    // arguments without a value number are kept as they are
     fn replace_args(&self, args: &Vec<String>) -> Vec<String> {
         return args
             .iter()
             .map(|arg| {
-                let num = self.var2num.get(arg).unwrap();
-                let var = self.num2var.get(num).unwrap();
-                var.clone()
+                self.var2num
+                    .get(arg)
+                    .and_then(|num| self.num2var.get(num))
+                    .unwrap_or(arg)
+                    .clone()
             })
             .collect();
     }
 
+    fn number_of(&self, var: &String, instr: &Instruction) -> Result<usize> {
+        self.var2num
+            .get(var)
+            .copied()
+            .ok_or_else(|| Error::UndefinedVariable {
+                func: self.func.clone(),
+                var: var.clone(),
+                pos: instruction_pos(instr),
+            })
+    }
+
+    fn numbers_of(&self, args: &[String], instr: &Instruction) -> Result<Vec<usize>> {
+        args.iter().map(|arg| self.number_of(arg, instr)).collect()
+    }
+
+    // the value numbering below indexes arguments by position
+    fn check_arity(&self, instr: &Instruction) -> Result<()> {
+        let (args, arity) = match instr {
+            Instruction::Constant { .. } => return Ok(()),
+            Instruction::Value { args, op, .. } => (args, value_arity(op)),
+            Instruction::Effect { args, op, .. } => (args, effect_arity(op)),
+        };
+        match arity {
+            Some(arity) if args.len() != arity => Err(Error::Malformed {
+                func: self.func.clone(),
+                msg: format!("`{}` takes {} arguments", instr, arity),
+                pos: instruction_pos(instr),
+            }),
+            _ => Ok(()),
+        }
+    }
+
     fn fold_value(&mut self, val_num: usize, canonical_val: &LVNValue) {
         match canonical_val {
             LVNValue::Constant(value) => {
@@ -156,11 +217,196 @@ impl LVN {
                     }
                 }
             }
+            LVNValue::PureCall(..) => {}
         }
     }
 
-    fn canonicalize_instruction(&self, instr: &Code) -> Option<(LVNValue, String, Type)> {
-        match instr {
+    // pointer value numbers may alias unless they are derived from different allocations in this
+    // block, or from the same allocation at different constant offsets
+    fn may_alias(&self, ptr0: usize, ptr1: usize, whole_allocation: bool) -> bool {
+        if ptr0 == ptr1 {
+            return true;
+        }
+        match (self.num2ptr.get(&ptr0), self.num2ptr.get(&ptr1)) {
+            (Some(info0), Some(info1)) => {
+                if info0.base != info1.base {
+                    return false;
+                }
+                match (info0.offset, info1.offset) {
+                    (Some(offset0), Some(offset1)) if !whole_allocation => offset0 == offset1,
+                    _ => true,
+                }
+            }
+            // a pointer from outside the block can only reach an allocation that has escaped
+            (Some(info), None) | (None, Some(info)) => self.escaped.contains(&info.base),
+            (None, None) => true,
+        }
+    }
+
+    fn must_alias(&self, ptr0: usize, ptr1: usize) -> bool {
+        if ptr0 == ptr1 {
+            return true;
+        }
+        match (self.num2ptr.get(&ptr0), self.num2ptr.get(&ptr1)) {
+            (Some(info0), Some(info1)) => {
+                info0.base == info1.base && info0.offset.is_some() && info0.offset == info1.offset
+            }
+            _ => false,
+        }
+    }
+
+    fn escape(&mut self, nums: &[usize]) {
+        for num in nums {
+            if let Some(info) = self.num2ptr.get(num) {
+                self.escaped.insert(info.base);
+            }
+        }
+    }
+
+    // forget everything stored at pointers that may alias `ptr`
+    fn clobber(&mut self, ptr: usize, whole_allocation: bool) {
+        let clobbered: Vec<usize> = self
+            .memory
+            .keys()
+            .cloned()
+            .filter(|&loc| self.may_alias(loc, ptr, whole_allocation))
+            .collect();
+        for loc in clobbered {
+            self.memory.remove(&loc);
+        }
+    }
+
+    fn load(&self, ptr: usize) -> Option<usize> {
+        self.memory
+            .iter()
+            .find(|(&loc, _)| self.must_alias(loc, ptr))
+            .map(|(_, &val)| val)
+    }
+
+    fn derive_pointer(&self, canonical_val: &LVNValue) -> Option<PointerInfo> {
+        if let LVNValue::ValueBinaryOp(ValueOps::PtrAdd, ptr, offset) = canonical_val {
+            let info = self.num2ptr.get(ptr)?;
+            let offset = match (info.offset, self.num2const.get(offset)) {
+                (Some(base_offset), Some(Literal::Int(offset))) => base_offset.checked_add(*offset),
+                _ => None,
+            };
+            return Some(PointerInfo {
+                base: info.base,
+                offset,
+            });
+        }
+        None
+    }
+
+    // value instructions that must never share a value number with another instruction
+    fn optimize_opaque_value(
+        &mut self,
+        instr: &Code,
+        dest: &String,
+        last_write: bool,
+    ) -> (Code, usize) {
+        // args have to be renamed before dest gets its new value number
+        let mut optimized = self.generate_optimized_instruction(instr, Some(dest.clone()));
+        let num = self.register_var(dest);
+        let new_dest = self.register_dest(dest, num, last_write);
+        if let Code::Instruction(Instruction::Value { dest, .. }) = &mut optimized {
+            *dest = new_dest;
+        }
+        (optimized, num)
+    }
+
+    // Handles allocations, loads, stores, frees and calls, which are not pure values.
+    // Returns None for instructions that go through regular value numbering.
+    fn optimize_memory_instruction(
+        &mut self,
+        instr: &Code,
+        last_write: bool,
+    ) -> Result<Option<Code>> {
+        let Code::Instruction(instr_inner) = instr else {
+            return Ok(None);
+        };
+        match instr_inner {
+            Instruction::Value {
+                args,
+                dest,
+                op,
+                op_type,
+                ..
+            } => match op {
+                ValueOps::Alloc => {
+                    let (optimized, num) = self.optimize_opaque_value(instr, dest, last_write);
+                    self.num2ptr.insert(
+                        num,
+                        PointerInfo {
+                            base: num,
+                            offset: Some(0),
+                        },
+                    );
+                    Ok(Some(optimized))
+                }
+                ValueOps::Load => {
+                    let ptr = self.number_of(&args[0], instr_inner)?;
+                    // store-to-load forwarding and redundant load elimination
+                    if let Some(val_num) = self.load(ptr) {
+                        self.var2num.insert(dest.clone(), val_num);
+                        if let Some(value) = self.get_const_if_fold(&val_num) {
+                            return Ok(Some(Self::generate_const_instruction(
+                                value,
+                                dest.clone(),
+                                instruction_pos(instr_inner),
+                            )));
+                        }
+                        return Ok(Some(self.generate_copy_instruction(
+                            &val_num,
+                            dest.clone(),
+                            op_type.clone(),
+                            instruction_pos(instr_inner),
+                        )?));
+                    }
+                    let (optimized, num) = self.optimize_opaque_value(instr, dest, last_write);
+                    self.memory.insert(ptr, num);
+                    Ok(Some(optimized))
+                }
+                ValueOps::Call => {
+                    let arg_nums = self.numbers_of(args, instr_inner)?;
+                    // even a pure callee may return a pointer derived from its arguments
+                    self.escape(&arg_nums);
+                    if is_pure_call(instr, self.summaries) {
+                        return Ok(None);
+                    }
+                    self.memory.clear();
+                    Ok(Some(self.optimize_opaque_value(instr, dest, last_write).0))
+                }
+                ValueOps::Phi => Ok(Some(self.optimize_opaque_value(instr, dest, last_write).0)),
+                _ => Ok(None),
+            },
+            Instruction::Effect { args, op, .. } => {
+                let arg_nums = self.numbers_of(args, instr_inner)?;
+                match op {
+                    EffectOps::Store => {
+                        self.escape(&[arg_nums[1]]);
+                        self.clobber(arg_nums[0], false);
+                        self.memory.insert(arg_nums[0], arg_nums[1]);
+                    }
+                    EffectOps::Free => self.clobber(arg_nums[0], true),
+                    EffectOps::Call if !is_pure_call(instr, self.summaries) => {
+                        self.escape(&arg_nums);
+                        self.memory.clear();
+                    }
+                    EffectOps::Return => self.escape(&arg_nums),
+                    _ => return Ok(None),
+                }
+                Ok(Some(self.generate_optimized_instruction(instr, None)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn canonicalize_instruction(&self, instr: &Code) -> Result<Option<(LVNValue, String, Type)>> {
+        let Code::Instruction(instr_inner) = instr else {
+            return Ok(None);
+        };
+        let canonical = match instr {
             Code::Instruction(Instruction::Constant {
                 value,
                 dest,
@@ -171,14 +417,26 @@ impl LVN {
                 dest.clone(),
                 const_type.clone(),
             )),
+            Code::Instruction(Instruction::Value {
+                args,
+                dest,
+                funcs,
+                op: ValueOps::Call,
+                op_type,
+                ..
+            }) if is_pure_call(instr, self.summaries) => Some((
+                LVNValue::PureCall(funcs[0].clone(), self.numbers_of(args, instr_inner)?),
+                dest.clone(),
+                op_type.clone(),
+            )),
             Code::Instruction(Instruction::Value {
                 args,
                 op,
                 dest,
                 op_type,
                 ..
-            }) if op == &ValueOps::Not || op == &ValueOps::Id => Some((
-                LVNValue::ValueUnaryOp(op.clone(), *self.var2num.get(&args[0]).unwrap()),
+            }) if (op == &ValueOps::Not || op == &ValueOps::Id) && args.len() == 1 => Some((
+                LVNValue::ValueUnaryOp(op.clone(), self.number_of(&args[0], instr_inner)?),
                 dest.clone(),
                 op_type.clone(),
             )),
@@ -188,10 +446,14 @@ impl LVN {
                 dest,
                 op_type,
                 ..
-            }) if op != &ValueOps::Not && op != &ValueOps::Id && op != &ValueOps::Call => {
+            }) if op != &ValueOps::Not
+                && op != &ValueOps::Id
+                && op != &ValueOps::Call
+                && args.len() == 2 =>
+            {
                 // canonicalize order of args for commutative ops
-                let mut arg_val0 = *self.var2num.get(&args[0]).unwrap();
-                let mut arg_val1 = *self.var2num.get(&args[1]).unwrap();
+                let mut arg_val0 = self.number_of(&args[0], instr_inner)?;
+                let mut arg_val1 = self.number_of(&args[1], instr_inner)?;
                 if op == &ValueOps::Add || op == &ValueOps::Mul {
                     if arg_val0 > arg_val1 {
                         let tmp = arg_val0.clone();
@@ -206,20 +468,22 @@ impl LVN {
                 ))
             }
             _ => None,
-        }
+        };
+        Ok(canonical)
     }
 
     fn calculate_binary_op(op: &ValueOps, arg0: &Literal, arg1: &Literal) -> Option<Literal> {
         match (arg0, arg1) {
             (Literal::Int(val0), Literal::Int(val1)) => match op {
-                ValueOps::Add => Some(Literal::Int(val0 + val1)),
-                ValueOps::Sub => Some(Literal::Int(val0 - val1)),
-                ValueOps::Mul => Some(Literal::Int(val0 * val1)),
+                // arithmetic wraps around, like in the interpreter
+                ValueOps::Add => Some(Literal::Int(val0.wrapping_add(*val1))),
+                ValueOps::Sub => Some(Literal::Int(val0.wrapping_sub(*val1))),
+                ValueOps::Mul => Some(Literal::Int(val0.wrapping_mul(*val1))),
                 ValueOps::Div => {
                     if *val1 == 0 {
                         None
                     } else {
-                        Some(Literal::Int(val0 / val1))
+                        Some(Literal::Int(val0.wrapping_div(*val1)))
                     }
                 }
                 ValueOps::Eq => Some(Literal::Bool(val0 == val1)),
@@ -258,24 +522,40 @@ impl LVN {
         }
     }
 
-    fn generate_copy_instruction(&self, value_number: &usize, dest: String, op_type: Type) -> Code {
-        let var = self.num2var.get(&value_number).unwrap().clone();
-        Code::Instruction(Instruction::Value {
-            args: vec![var],
+    // rewritten instructions keep the position of the instruction they replace
+    fn generate_copy_instruction(
+        &self,
+        value_number: &usize,
+        dest: String,
+        op_type: Type,
+        pos: Option<Position>,
+    ) -> Result<Code> {
+        let Some(var) = self.num2var.get(&value_number) else {
+            return Err(Error::Malformed {
+                func: self.func.clone(),
+                msg: format!(
+                    "no variable holds value number {} of {}",
+                    value_number, dest
+                ),
+                pos,
+            });
+        };
+        Ok(Code::Instruction(Instruction::Value {
+            args: vec![var.clone()],
             dest: dest,
             funcs: vec![],
             labels: vec![],
             op: ValueOps::Id,
-            pos: None,
+            pos,
             op_type: op_type,
-        })
+        }))
     }
 
-    fn generate_const_instruction(value: &Literal, dest: String) -> Code {
+    fn generate_const_instruction(value: &Literal, dest: String, pos: Option<Position>) -> Code {
         Code::Instruction(Instruction::Constant {
             dest: dest,
             op: ConstOps::Const,
-            pos: None,
+            pos,
             const_type: match value {
                 Literal::Bool(_) => Type::Bool,
                 Literal::Int(_) => Type::Int,
@@ -288,14 +568,14 @@ impl LVN {
         match instr {
             Code::Label { .. } => return instr.clone(),
             Code::Instruction(Instruction::Constant {
+                dest: old_dest,
                 op,
                 pos,
                 const_type,
                 value,
-                ..
             }) => {
                 return Code::Instruction(Instruction::Constant {
-                    dest: dest.unwrap(),
+                    dest: dest.unwrap_or_else(|| old_dest.clone()),
                     op: op.clone(),
                     pos: pos.clone(),
                     const_type: const_type.clone(),
@@ -304,16 +584,16 @@ impl LVN {
             }
             Code::Instruction(Instruction::Value {
                 args,
+                dest: old_dest,
                 funcs,
                 labels,
                 op,
                 pos,
                 op_type,
-                ..
             }) => {
                 return Code::Instruction(Instruction::Value {
                     args: self.replace_args(args),
-                    dest: dest.unwrap(),
+                    dest: dest.unwrap_or_else(|| old_dest.clone()),
                     funcs: funcs.clone(),
                     labels: labels.clone(),
                     op: op.clone(),
@@ -339,38 +619,54 @@ impl LVN {
         }
     }
 
-    pub fn optimize_instruction(&mut self, instr: &Code, last_write: bool) -> Code {
+    // fails on instructions with the wrong number of arguments or undefined arguments
+    pub fn optimize_instruction(&mut self, instr: &Code, last_write: bool) -> Result<Code> {
+        if let Code::Instruction(instr_inner) = instr {
+            self.check_arity(instr_inner)?;
+        }
+        if let Some(optimized) = self.optimize_memory_instruction(instr, last_write)? {
+            return Ok(optimized);
+        }
+
         // Get canonical value of instruction (if instruction is a value instruction)
-        let canonical_val = self.canonicalize_instruction(instr);
+        let canonical_val = self.canonicalize_instruction(instr)?;
+        let pos = match instr {
+            Code::Instruction(instr) => instruction_pos(instr),
+            Code::Label { .. } => None,
+        };
 
         let mut new_dest: Option<String> = None;
         if let Some((canonical_val, dest, op_type)) = canonical_val {
             // Copy propagation
             if let LVNValue::ValueUnaryOp(ValueOps::Id, val_num) = canonical_val {
                 self.var2num.insert(dest.clone(), val_num);
-                return self.generate_copy_instruction(&val_num, dest, op_type);
+                return self.generate_copy_instruction(&val_num, dest, op_type, pos);
             }
 
             // check if value has been seen already
             if let Some(val_num) = self.val2num.get(&canonical_val).cloned() {
                 self.var2num.insert(dest.clone(), val_num);
                 if let Some(value) = self.get_const_if_fold(&val_num) {
-                    return Self::generate_const_instruction(value, dest);
+                    return Ok(Self::generate_const_instruction(value, dest, pos));
                 } else {
-                    return self.generate_copy_instruction(&val_num, dest, op_type);
+                    return self.generate_copy_instruction(&val_num, dest, op_type, pos);
                 }
             } else {
+                let pointer = self.derive_pointer(&canonical_val);
                 let (dest, val_num) = self.register_val(&dest, canonical_val, last_write);
                 new_dest = Some(dest.clone());
+                if let Some(info) = pointer {
+                    self.num2ptr.insert(val_num, info);
+                }
 
                 // fold value if possible
                 if let Some(value) = self.get_const_if_fold(&val_num) {
-                    return Self::generate_const_instruction(value, dest);
+                    return Ok(Self::generate_const_instruction(value, dest, pos));
                 }
             }
         }
 
         // Replace args in instruction
-        return self.generate_optimized_instruction(instr, new_dest);
+        return Ok(self.generate_optimized_instruction(instr, new_dest));
     }
 }
@@ -1,225 +1,779 @@
-use std::fs::File;
+use std::{
+    fmt::{Display, Write},
+    fs::File,
+    path::{Path, PathBuf},
+};
 
-use bril_rs::{load_program, load_program_from_read, Function};
+use bril_rs::{
+    load_program, load_program_from_read, Code, EffectOps, Function, Instruction, Program, ValueOps,
+};
 
 use brilopt::{
-    analyze::{dominance_frontier, dominator_tree, dominators, reaching_definitions},
-    optimize::{dead_store_elim, dead_variable_elim, lvn_block},
-    parse::{
-        basic_blocks, block_name_to_idx, control_flow_graph, expanded_basic_blocks, get_block_name,
+    alias::{is_pointer, AliasAnalysis},
+    analysis_manager::AnalysisManager,
+    analyze::{
+        dominance_frontier, dominator_tree, dominators, natural_loops, reaching_definitions,
     },
-    ssa::convert_to_ssa,
+    callgraph::{bottom_up_order, call_graph, recursive_functions},
+    check::compare,
+    dfe::dead_function_elim,
+    fuzz::fuzz,
+    generate::random_program,
+    induction::{induction_variables, LoopContext},
+    interp::interpret,
+    link::{link, LinkedProgram},
+    memcheck::memcheck,
+    parse::{block_name_to_idx, control_flow_graph, expanded_basic_blocks, get_block_name},
+    pass::PassManager,
+    reduce::{reduce_for_pipeline, Predicate},
     util::graphviz,
 };
 
-const DEBUG_FILEPATH: &str = "/Users/bvonhofe/Desktop/bril/bril-rs/brilopt/test/loop-orig.json";
+// Modes that run a pipeline of passes: (mode, pipeline, heading of the transformed program).
+// Without a heading, the transformed functions are printed one by one.
+const PRESETS: [(&str, &str, Option<&str>); 7] = [
+    ("opt", "lvn,dce,dse", Some("optimized")),
+    ("fold", "fold", Some("folded")),
+    ("foldopt", "fold,dce,dse", Some("optimized")),
+    ("dse", "dse", Some("optimized")),
+    ("ssa", "ssa", None),
+    ("mem2reg", "mem2reg", None),
+    ("memdse", "memdse", None),
+];
 
-fn main() {
-    let mut args = std::env::args();
-    args.next();
-    let mode = args.next().unwrap_or(String::from("dbg")).to_lowercase();
-
-    match mode.as_str() {
-        "main" => {
-            let prog = load_program();
-            println!("{}", &prog);
-        }
-        "ssa" => {
-            let prog = load_program();
+// modes that print something other than a transformed program, in the order they are listed
+const ANALYSIS_MODES: [(&str, fn(&Options) -> String); 13] = [
+    ("main", main_mode),
+    ("cfg", cfg_mode),
+    ("callgraph", callgraph_mode),
+    ("dfe", dfe_mode),
+    ("domtree", domtree_mode),
+    ("reach", reach_mode),
+    ("dom", dom_mode),
+    ("domfront", domfront_mode),
+    ("alias", alias_mode),
+    ("ivs", ivs_mode),
+    ("memcheck", memcheck_mode),
+    ("interp", interp_mode),
+    ("generate", generate_mode),
+];
+
+// modes that decide whether brilopt succeeds, returning what they print and whether it did
+const DRIVER_MODES: [(&str, fn(&Options) -> (String, bool)); 3] = [
+    ("check", check),
+    ("fuzz", fuzz_mode),
+    ("reduce", |opts| (reduce(opts), true)),
+];
+
+#[derive(PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+struct Options {
+    mode: Option<String>,
+    pipeline: Option<String>,
+    verify: bool,
+    input: Option<String>,
+    output: Option<String>,
+    format: Format,
+    functions: Vec<String>,
+    roots: Vec<String>,
+    optimized_only: bool,
+    dump_after: Vec<String>,
+    dump_file: Option<String>,
+    search_path: Vec<PathBuf>,
+    modules_dir: Option<String>,
+    args: Vec<String>,
+    seed: u64,
+    count: u64,
+    predicate: Predicate,
+}
 
-            for func in prog.functions.iter() {
-                println!("{}\n", convert_to_ssa(func));
+const USAGE: &str = "usage: brilopt [MODE | -p PIPELINE [--verify]] [OPTIONS] [FILE]
+       brilopt check (PRESET | -p PIPELINE) [OPTIONS] [FILE]
+       brilopt reduce (PRESET | -p PIPELINE) [--predicate PREDICATE] [OPTIONS] [FILE]
+       brilopt fuzz [--seed N] [--count N]
+       brilopt generate [--seed N]
+
+Reads a Bril JSON program from FILE, or from stdin if FILE is missing or `-`. Imported files
+are looked up relative to the importing file, or to the current directory for a program from
+stdin, then in the search path, and linked into one program. Imported files ending in `.bril`
+are read as text with bril2json, others as JSON.
+
+options:
+  -o, --output FILE     write to FILE instead of stdout
+  -f, --format FORMAT   print programs as `text` (default) or `json`
+  --func NAME           only print function NAME, may be repeated
+  --root NAME           dfe keeps the functions reachable from NAME instead of from main, may
+                        be repeated
+  --optimized-only      only print the transformed program, not the original
+  --dump-after PASS     print the program after every run of PASS, or of every pass if PASS is
+                        `all`, to stderr; may be repeated
+  --dump-file FILE      write the --dump-after programs to FILE instead of stderr
+  -I, --import-path DIR add DIR to the search path for imported files, may be repeated
+  --modules DIR         with -p, write each file of the transformed program to DIR instead of
+                        printing the linked program, and print the paths written; the
+                        program from stdin is written to `stdin.bril` or `stdin.json`, and
+                        imported files keep their place relative to it
+  -a, --arg VALUE       pass VALUE to @main in the interp, check and reduce modes, may be
+                        repeated
+  --predicate PREDICATE what reduce preserves: `mismatch` (default) when the pipeline changes
+                        what the program prints or returns, `panic` when a pass panics
+  --seed N              generate the program, or the first fuzz program, from seed N (default
+                        0)
+  --count N             number of programs to fuzz (default 100)
+  -h, --help            print this message";
+
+fn fail(msg: impl Display) -> ! {
+    eprintln!("error: {}", msg);
+    std::process::exit(1);
+}
+
+// the value given as `--flag=value` or as the next argument
+fn value_of(
+    flag: &str,
+    inline: &mut Option<String>,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<String, String> {
+    inline
+        .take()
+        .or_else(|| args.next())
+        .ok_or_else(|| format!("{} needs a value", flag))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut opts = Options {
+        mode: None,
+        pipeline: None,
+        verify: false,
+        input: None,
+        output: None,
+        format: Format::Text,
+        functions: vec![],
+        roots: vec![],
+        optimized_only: false,
+        dump_after: vec![],
+        dump_file: None,
+        search_path: vec![],
+        modules_dir: None,
+        args: vec![],
+        seed: 0,
+        count: 100,
+        predicate: Predicate::Mismatch,
+    };
+    let mut positional = vec![];
+    while let Some(mut arg) = args.next() {
+        let mut inline = None;
+        if arg.starts_with("--") {
+            if let Some((flag, value)) = arg.clone().split_once('=') {
+                inline = Some(String::from(value));
+                arg = String::from(flag);
             }
         }
-        "cfg" => {
-            let prog = load_program();
-            for func in prog.functions.iter() {
-                println!(
-                    "{}",
-                    graphviz(&control_flow_graph(func), &func.name).unwrap()
-                );
-                break;
+        match arg.as_str() {
+            "-p" | "--passes" => opts.pipeline = Some(value_of(&arg, &mut inline, &mut args)?),
+            "--verify" => opts.verify = true,
+            "-o" | "--output" => opts.output = Some(value_of(&arg, &mut inline, &mut args)?),
+            "-f" | "--format" => {
+                opts.format = match value_of(&arg, &mut inline, &mut args)?.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    format => {
+                        return Err(format!(
+                            "unknown format '{}', expected text or json",
+                            format
+                        ))
+                    }
+                }
             }
+            "--root" => {
+                let name = value_of(&arg, &mut inline, &mut args)?;
+                opts.roots.push(String::from(name.trim_start_matches('@')));
+            }
+            "--func" => {
+                let name = value_of(&arg, &mut inline, &mut args)?;
+                opts.functions
+                    .push(String::from(name.trim_start_matches('@')));
+            }
+            "--optimized-only" => opts.optimized_only = true,
+            "--dump-after" => opts
+                .dump_after
+                .push(value_of(&arg, &mut inline, &mut args)?),
+            "-a" | "--arg" => opts.args.push(value_of(&arg, &mut inline, &mut args)?),
+            "--dump-file" => opts.dump_file = Some(value_of(&arg, &mut inline, &mut args)?),
+            "-I" | "--import-path" => {
+                opts.search_path
+                    .push(PathBuf::from(value_of(&arg, &mut inline, &mut args)?))
+            }
+            "--modules" => opts.modules_dir = Some(value_of(&arg, &mut inline, &mut args)?),
+            "--predicate" => {
+                opts.predicate = match value_of(&arg, &mut inline, &mut args)?.as_str() {
+                    "mismatch" => Predicate::Mismatch,
+                    "panic" => Predicate::Panic,
+                    predicate => {
+                        return Err(format!(
+                            "unknown predicate '{}', expected mismatch or panic",
+                            predicate
+                        ))
+                    }
+                }
+            }
+            "--seed" | "--count" => {
+                let value = value_of(&arg, &mut inline, &mut args)?;
+                let number = value
+                    .parse()
+                    .map_err(|_| format!("{} needs a number, not '{}'", arg, value))?;
+                if arg == "--seed" {
+                    opts.seed = number;
+                } else {
+                    opts.count = number;
+                }
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "-" => positional.push(arg),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ => positional.push(arg),
+        }
+        if inline.is_some() {
+            return Err(format!("{} takes no value", arg));
         }
-        "domtree" => {
-            for func in load_program().functions.iter() {
-                println!(
-                    "{}",
-                    graphviz(
-                        &dominator_tree(func),
-                        &format!("{}_dominator_tree", &func.name)
+    }
+
+    // the mode comes first, unless a pipeline takes its place. `check` and `reduce` are followed
+    // by the preset to run, unless they run a pipeline.
+    let mut positional = positional.into_iter().peekable();
+    let takes_pipeline = |mode: &str| mode == "check" || mode == "reduce";
+    if opts.pipeline.is_none()
+        || positional
+            .peek()
+            .is_some_and(|arg| takes_pipeline(arg.as_str()))
+    {
+        opts.mode = positional.next().map(|mode| mode.to_lowercase());
+    }
+    if opts.mode.as_deref().is_some_and(takes_pipeline) && opts.pipeline.is_none() {
+        let mode = opts.mode.as_ref().unwrap();
+        let preset = positional
+            .next()
+            .ok_or_else(|| format!("{} needs a preset or -p PIPELINE", mode))?;
+        let (_, pipeline, _) = PRESETS
+            .iter()
+            .find(|(name, ..)| *name == preset)
+            .ok_or_else(|| format!("unknown preset '{}' to {}", preset, mode))?;
+        opts.pipeline = Some(String::from(*pipeline));
+    }
+    opts.input = positional.next().filter(|path| path != "-");
+    if let Some(arg) = positional.next() {
+        return Err(format!("unexpected argument '{}'", arg));
+    }
+    Ok(opts)
+}
+
+// the input program with its imports linked in
+fn load_linked(opts: &Options) -> LinkedProgram {
+    let prog = match &opts.input {
+        Some(path) => load_program_from_read(
+            File::open(path).unwrap_or_else(|err| fail(format!("cannot open {}: {}", path, err))),
+        ),
+        None => load_program(),
+    };
+    // `-` has no parent directory, so the imports of stdin resolve against the current directory
+    let root_path = Path::new(opts.input.as_deref().unwrap_or("-"));
+    let linked = link(prog, root_path, &opts.search_path).unwrap_or_else(fail);
+    if let Some(name) = opts
+        .functions
+        .iter()
+        .find(|&name| !linked.prog.functions.iter().any(|func| &func.name == name))
+    {
+        fail(format!("no function @{} in the program", name));
+    }
+    linked
+}
+
+fn load(opts: &Options) -> Program {
+    load_linked(opts).prog
+}
+
+// writes every file of the program to `dir`, and returns the paths written
+fn write_modules(linked: &LinkedProgram, prog: &Program, dir: &str, opts: &Options) -> String {
+    let mut out = String::new();
+    let extension = match opts.format {
+        Format::Text => "bril",
+        Format::Json => "json",
+    };
+    for (path, module) in linked.split(prog, extension) {
+        let file = Path::new(dir).join(path);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)
+                .unwrap_or_else(|err| fail(format!("cannot create {}: {}", parent.display(), err)));
+        }
+        std::fs::write(&file, format_program(&module, opts))
+            .unwrap_or_else(|err| fail(format!("cannot write {}: {}", file.display(), err)));
+        writeln!(out, "{}", file.display()).unwrap();
+    }
+    out
+}
+
+// the functions selected with --func, or all of them
+fn selected<'a>(prog: &'a Program, opts: &'a Options) -> impl Iterator<Item = &'a Function> {
+    prog.functions
+        .iter()
+        .filter(|func| opts.functions.is_empty() || opts.functions.contains(&func.name))
+}
+
+fn format_program(prog: &Program, opts: &Options) -> String {
+    let mut filtered = prog.clone();
+    filtered.functions = selected(prog, opts).cloned().collect();
+    match opts.format {
+        Format::Text => filtered.to_string(),
+        Format::Json => serde_json::to_string_pretty(&filtered).unwrap() + "\n",
+    }
+}
+
+fn run_pipeline(pipeline: &str, verify: bool, opts: &Options, prog: &Program) -> Program {
+    let mut manager = PassManager::parse(pipeline, verify).unwrap_or_else(fail);
+    if !opts.dump_after.is_empty() {
+        let out: Box<dyn std::io::Write> = match &opts.dump_file {
+            Some(path) => Box::new(
+                File::create(path)
+                    .unwrap_or_else(|err| fail(format!("cannot create {}: {}", path, err))),
+            ),
+            None => Box::new(std::io::stderr()),
+        };
+        manager
+            .dump_after(opts.dump_after.clone(), out)
+            .unwrap_or_else(fail);
+    }
+    manager.run(prog).unwrap_or_else(fail)
+}
+
+fn main() {
+    let opts = parse_args(std::env::args().skip(1))
+        .unwrap_or_else(|msg| fail(format!("{}\n\n{}", msg, USAGE)));
+    let driver = DRIVER_MODES
+        .iter()
+        .find(|(name, _)| opts.mode.as_deref() == Some(*name));
+    let (out, ok) = match driver {
+        Some((_, mode_fn)) => mode_fn(&opts),
+        None => (run(&opts), true),
+    };
+    match &opts.output {
+        Some(path) => std::fs::write(path, out)
+            .unwrap_or_else(|err| fail(format!("cannot write {}: {}", path, err))),
+        None => print!("{}", out),
+    }
+    if !ok {
+        std::process::exit(1);
+    }
+}
+
+// Runs the program before and after the pipeline and compares what they print and return. Not
+// ok if they disagree.
+fn check(opts: &Options) -> (String, bool) {
+    let prog = load(opts);
+    let pipeline = opts.pipeline.as_ref().unwrap();
+    let new_prog = run_pipeline(pipeline, opts.verify, opts, &prog);
+    let comparison = compare(&prog, &new_prog, &opts.args);
+    (format!("{}\n", comparison), comparison.agrees())
+}
+
+// the smallest program found on which the predicate still holds for the pipeline
+fn reduce(opts: &Options) -> String {
+    let prog = load(opts);
+    let pipeline = PassManager::parse(opts.pipeline.as_ref().unwrap(), false).unwrap_or_else(fail);
+    let reduced =
+        reduce_for_pipeline(&prog, &pipeline, opts.predicate, &opts.args).unwrap_or_else(|| {
+            fail(match opts.predicate {
+                Predicate::Mismatch => "the pipeline does not change what the program does",
+                Predicate::Panic => "no pass of the pipeline panics on the program",
+            })
+        });
+    format_program(&reduced, opts)
+}
+
+// Runs the fuzz pipelines on generated programs and prints the ones they break. Not ok if any
+// broke.
+fn fuzz_mode(opts: &Options) -> (String, bool) {
+    let failures = fuzz(opts.seed, opts.count);
+    let mut out = String::new();
+    for failure in failures.iter() {
+        writeln!(out, "{}", failure).unwrap();
+    }
+    writeln!(out, "{} of {} programs failed", failures.len(), opts.count).unwrap();
+    (out, failures.is_empty())
+}
+
+// For every load, store and free, the pointer variables defined at that point that must or may
+// refer to the same memory cell as its pointer, or for frees, to the same allocation
+fn write_aliases(func: &Function, out: &mut String) {
+    let alias = AliasAnalysis::new(func, &mut AnalysisManager::new()).unwrap_or_else(fail);
+    let mut pointers: Vec<&String> = func
+        .args
+        .iter()
+        .filter(|arg| is_pointer(&arg.arg_type))
+        .map(|arg| &arg.name)
+        .chain(func.instrs.iter().filter_map(|code| match code {
+            Code::Instruction(Instruction::Value { dest, op_type, .. }) if is_pointer(op_type) => {
+                Some(dest)
+            }
+            _ => None,
+        }))
+        .collect();
+    pointers.sort();
+    pointers.dedup();
+
+    for (idx, block) in expanded_basic_blocks(func).iter().enumerate() {
+        let name = get_block_name(block, idx, &func.name);
+        for (line, code) in block.iter().enumerate() {
+            let (access, args) = match code {
+                Code::Instruction(Instruction::Value {
+                    args,
+                    op: ValueOps::Load,
+                    ..
+                }) => ("load", args),
+                Code::Instruction(Instruction::Effect {
+                    args,
+                    op: EffectOps::Store,
+                    ..
+                }) => ("store", args),
+                Code::Instruction(Instruction::Effect {
+                    args,
+                    op: EffectOps::Free,
+                    ..
+                }) => ("free", args),
+                _ => continue,
+            };
+            let Some(ptr) = args.first() else {
+                continue;
+            };
+            let whole_allocation = access == "free";
+            let (mut must, mut may) = (vec![], vec![]);
+            for &other in pointers.iter().filter(|&&other| other != ptr) {
+                let defined = func.args.iter().any(|arg| &arg.name == other)
+                    || !alias.reaching_at(other, &name, line).is_empty();
+                if !defined {
+                    continue;
+                }
+                let (is_must, is_may) = if whole_allocation {
+                    (
+                        alias.must_alias_allocation(ptr, other, &name, line),
+                        alias.may_alias_allocation(ptr, other, &name, line),
                     )
-                    .unwrap()
-                );
-                break;
+                } else {
+                    (
+                        alias.must_alias(ptr, other, &name, line),
+                        alias.may_alias(ptr, other, &name, line),
+                    )
+                };
+                if is_must {
+                    must.push(other.as_str());
+                } else if is_may {
+                    may.push(other.as_str());
+                }
             }
-        }
-        "opt" => {
-            let prog = load_program();
 
-            let mut opt_prog = prog.clone();
-            opt_prog.functions = opt_prog
-                .functions
-                .iter()
-                .map(|func| Function {
-                    args: func.args.clone(),
-                    instrs: basic_blocks(&func)
-                        .iter()
-                        .flat_map(|block| lvn_block(block, false))
-                        .collect(),
-                    name: func.name.clone(),
-                    pos: func.pos.clone(),
-                    return_type: func.return_type.clone(),
-                })
-                .map(|func| dead_variable_elim(&func))
-                .map(|func| Function {
-                    args: func.args.clone(),
-                    instrs: basic_blocks(&func)
-                        .iter()
-                        .flat_map(|block| dead_store_elim(block))
-                        .collect(),
-                    name: func.name.clone(),
-                    pos: func.pos.clone(),
-                    return_type: func.return_type.clone(),
-                })
-                .collect();
-
-            println!("[original] {}\n[optimized] {}", &prog, &opt_prog);
+            let mut answers = vec![];
+            if !must.is_empty() {
+                answers.push(format!("must {}", must.join(", ")));
+            }
+            if !may.is_empty() {
+                answers.push(format!("may {}", may.join(", ")));
+            }
+            if answers.is_empty() {
+                answers.push(String::from("no aliases"));
+            }
+            writeln!(out, "  {} {}: {}", access, ptr, answers.join("; ")).unwrap();
         }
-        "fold" => {
-            let prog = load_program();
+    }
+}
 
-            let mut opt_prog = prog.clone();
-            opt_prog.functions = opt_prog
-                .functions
-                .iter()
-                .map(|func| Function {
-                    args: func.args.clone(),
-                    instrs: basic_blocks(&func)
-                        .iter()
-                        .flat_map(|block| lvn_block(block, true))
-                        .collect(),
-                    name: func.name.clone(),
-                    pos: func.pos.clone(),
-                    return_type: func.return_type.clone(),
-                })
-                .collect();
-
-            println!("[original] {}\n[folded] {}", &prog, &opt_prog);
+// returns everything the mode prints
+fn run(opts: &Options) -> String {
+    let mut out = String::new();
+
+    if let Some(pipeline) = &opts.pipeline {
+        let linked = load_linked(opts);
+        let new_prog = run_pipeline(pipeline, opts.verify, opts, &linked.prog);
+        if let Some(dir) = &opts.modules_dir {
+            return write_modules(&linked, &new_prog, dir, opts);
         }
-        "foldopt" => {
-            let prog = load_program();
+        return format_program(&new_prog, opts);
+    }
 
-            let mut opt_prog = prog.clone();
-            opt_prog.functions = opt_prog
-                .functions
-                .iter()
-                .map(|func| Function {
-                    args: func.args.clone(),
-                    instrs: basic_blocks(&func)
-                        .iter()
-                        .flat_map(|block| lvn_block(block, true))
-                        .collect(),
-                    name: func.name.clone(),
-                    pos: func.pos.clone(),
-                    return_type: func.return_type.clone(),
-                })
-                .map(|func| dead_variable_elim(&func))
-                .map(|func| Function {
-                    args: func.args.clone(),
-                    instrs: basic_blocks(&func)
-                        .iter()
-                        .flat_map(|block| dead_store_elim(block))
-                        .collect(),
-                    name: func.name.clone(),
-                    pos: func.pos.clone(),
-                    return_type: func.return_type.clone(),
-                })
-                .collect();
-
-            println!("[original] {}\n[optimized] {}", &prog, &opt_prog);
+    let Some(mode) = &opts.mode else {
+        fail(format!("no mode given\n\n{}", USAGE));
+    };
+    if let Some((_, pipeline, heading)) = PRESETS.iter().find(|(name, ..)| name == mode) {
+        let prog = load(opts);
+        let new_prog = run_pipeline(pipeline, false, opts, &prog);
+
+        // JSON has no room for headings, so it always gets just the transformed program
+        if opts.optimized_only || opts.format == Format::Json {
+            return format_program(&new_prog, opts);
         }
-        "reach" => {
-            let prog = load_program();
-
-            for func in prog.functions.iter() {
-                let reaching = reaching_definitions(func);
-                for (i, b) in expanded_basic_blocks(func).iter().enumerate() {
-                    let block = get_block_name(&b, i, &func.name);
-                    let (inputs, outputs) = &reaching[&block];
-
-                    let mut inputs_str = inputs
-                        .iter()
-                        .map(|def| {
-                            def.name.clone() + "_" + &def.block + "_" + &def.line.to_string()
-                        })
-                        .collect::<Vec<String>>();
-                    inputs_str.sort();
-
-                    let mut outputs_str = outputs
-                        .iter()
-                        .map(|def| {
-                            def.name.clone() + "_" + &def.block + "_" + &def.line.to_string()
-                        })
-                        .collect::<Vec<String>>();
-                    outputs_str.sort();
-
-                    println!(
-                        "{}:\n  in:  {}\n  out: {}",
-                        block,
-                        inputs_str.join(" "),
-                        outputs_str.join(" ")
-                    );
+        match heading {
+            Some(heading) => writeln!(
+                out,
+                "[original] {}\n[{}] {}",
+                format_program(&prog, opts),
+                heading,
+                format_program(&new_prog, opts)
+            )
+            .unwrap(),
+            None => {
+                for func in selected(&new_prog, opts) {
+                    writeln!(out, "{}\n", func).unwrap();
                 }
-                println!("");
             }
         }
-        "dom" => {
-            let prog = load_program();
+        return out;
+    }
 
-            for func in prog.functions.iter() {
-                let name2idx = block_name_to_idx(func);
+    let Some((_, mode_fn)) = ANALYSIS_MODES.iter().find(|(name, _)| name == mode) else {
+        let modes: Vec<&str> = PRESETS
+            .iter()
+            .map(|(name, ..)| *name)
+            .chain(ANALYSIS_MODES.iter().map(|(name, _)| *name))
+            .chain(DRIVER_MODES.iter().map(|(name, _)| *name))
+            .collect();
+        fail(format!(
+            "unknown mode '{}', expected one of {}",
+            mode,
+            modes.join(", ")
+        ));
+    };
+    mode_fn(opts)
+}
 
-                println!("{}", &func.name);
-                let dom_map = dominators(func);
-                let mut blocks: Vec<String> = dom_map.keys().cloned().collect();
-                blocks.sort_by(|a, b| name2idx[a].cmp(&name2idx[b]));
+fn main_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let prog = load(opts);
+    writeln!(out, "{}", format_program(&prog, opts)).unwrap();
+    out
+}
 
-                for block in blocks.iter() {
-                    let mut doms: Vec<String> = dom_map[block].clone().into_iter().collect();
-                    doms.sort_by(|a, b| name2idx[a].cmp(&name2idx[b]));
-                    println!("  {}: {:?}", block, doms);
-                }
-                println!("");
-            }
+fn cfg_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let prog = load(opts);
+    for func in selected(&prog, opts) {
+        writeln!(
+            out,
+            "{}",
+            graphviz(&control_flow_graph(func).unwrap_or_else(fail), &func.name).unwrap()
+        )
+        .unwrap();
+        break;
+    }
+    out
+}
+
+fn callgraph_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let graph = call_graph(&load(opts));
+    writeln!(
+        out,
+        "{}",
+        graphviz(&graph, &String::from("callgraph")).unwrap()
+    )
+    .unwrap();
+    // as comments, so that the output is still a graphviz file
+    writeln!(out, "// bottom-up: {}", bottom_up_order(&graph).join(", ")).unwrap();
+    let mut recursive: Vec<String> = recursive_functions(&graph).into_iter().collect();
+    if !recursive.is_empty() {
+        recursive.sort();
+        writeln!(out, "// recursive: {}", recursive.join(", ")).unwrap();
+    }
+    out
+}
+
+fn domtree_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let prog = load(opts);
+    for func in selected(&prog, opts) {
+        writeln!(
+            out,
+            "{}",
+            graphviz(
+                &dominator_tree(func).unwrap_or_else(fail),
+                &format!("{}_dominator_tree", &func.name)
+            )
+            .unwrap()
+        )
+        .unwrap();
+        break;
+    }
+    out
+}
+
+fn reach_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let prog = load(opts);
+
+    for func in selected(&prog, opts) {
+        let reaching = reaching_definitions(func).unwrap_or_else(fail);
+        for (i, b) in expanded_basic_blocks(func).iter().enumerate() {
+            let block = get_block_name(&b, i, &func.name);
+            let (inputs, outputs) = &reaching[&block];
+
+            let mut inputs_str = inputs
+                .iter()
+                .map(|def| def.name.clone() + "_" + &def.block + "_" + &def.line.to_string())
+                .collect::<Vec<String>>();
+            inputs_str.sort();
+
+            let mut outputs_str = outputs
+                .iter()
+                .map(|def| def.name.clone() + "_" + &def.block + "_" + &def.line.to_string())
+                .collect::<Vec<String>>();
+            outputs_str.sort();
+
+            writeln!(
+                out,
+                "{}:\n  in:  {}\n  out: {}",
+                block,
+                inputs_str.join(" "),
+                outputs_str.join(" ")
+            )
+            .unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+    out
+}
+
+fn dom_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let prog = load(opts);
+
+    for func in selected(&prog, opts) {
+        let name2idx = block_name_to_idx(func);
+
+        writeln!(out, "{}", &func.name).unwrap();
+        let dom_map = dominators(func).unwrap_or_else(fail);
+        let mut blocks: Vec<String> = dom_map.keys().cloned().collect();
+        blocks.sort_by(|a, b| name2idx[a].cmp(&name2idx[b]));
+
+        for block in blocks.iter() {
+            let mut doms: Vec<String> = dom_map[block].clone().into_iter().collect();
+            doms.sort_by(|a, b| name2idx[a].cmp(&name2idx[b]));
+            writeln!(out, "  {}: {:?}", block, doms).unwrap();
         }
-        "domfront" => {
-            let prog = load_program();
+        writeln!(out).unwrap();
+    }
+    out
+}
 
-            for func in prog.functions.iter() {
-                let name2idx = block_name_to_idx(func);
+fn domfront_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let prog = load(opts);
 
-                println!("{}", &func.name);
-                let dom_map = dominance_frontier(func);
-                let mut blocks: Vec<String> = dom_map.keys().cloned().collect();
-                blocks.sort_by(|a, b| name2idx[a].cmp(&name2idx[b]));
+    for func in selected(&prog, opts) {
+        let name2idx = block_name_to_idx(func);
 
-                for block in blocks.iter() {
-                    let mut doms: Vec<String> = dom_map[block].clone().into_iter().collect();
-                    doms.sort_by(|a, b| name2idx[a].cmp(&name2idx[b]));
-                    println!("  {}: {:?}", block, doms);
-                }
-                println!("");
-            }
+        writeln!(out, "{}", &func.name).unwrap();
+        let dom_map = dominance_frontier(func).unwrap_or_else(fail);
+        let mut blocks: Vec<String> = dom_map.keys().cloned().collect();
+        blocks.sort_by(|a, b| name2idx[a].cmp(&name2idx[b]));
+
+        for block in blocks.iter() {
+            let mut doms: Vec<String> = dom_map[block].clone().into_iter().collect();
+            doms.sort_by(|a, b| name2idx[a].cmp(&name2idx[b]));
+            writeln!(out, "  {}: {:?}", block, doms).unwrap();
         }
-        _ => {
-            println!("[DEBUG MODE] Reading program from {}\n", DEBUG_FILEPATH);
-            let debug_file = File::open(DEBUG_FILEPATH).unwrap();
-            let prog = load_program_from_read(debug_file);
+        writeln!(out).unwrap();
+    }
+    out
+}
+
+fn alias_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let prog = load(opts);
+
+    for func in selected(&prog, opts) {
+        writeln!(out, "{}", &func.name).unwrap();
+        write_aliases(func, &mut out);
+        writeln!(out).unwrap();
+    }
+    out
+}
 
-            for func in prog.functions.iter() {
-                println!("{}\n", convert_to_ssa(func));
+fn ivs_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let prog = load(opts);
+
+    for func in selected(&prog, opts) {
+        writeln!(out, "{}", &func.name).unwrap();
+        let reaching = reaching_definitions(func).unwrap_or_else(fail);
+        for natural_loop in natural_loops(func).unwrap_or_else(fail).iter() {
+            writeln!(out, "  {}:", natural_loop.header).unwrap();
+            let ctx = LoopContext::new(func, natural_loop, &reaching);
+            let ivs = induction_variables(&ctx);
+            let mut vars: Vec<&String> = ivs.keys().collect();
+            vars.sort();
+            for var in vars {
+                let iv = &ivs[var];
+                if &iv.base == var {
+                    writeln!(out, "    {}: step {}", var, iv.step).unwrap();
+                } else {
+                    writeln!(
+                        out,
+                        "    {}: {} * {} + {}",
+                        var, iv.scale, iv.base, iv.offset
+                    )
+                    .unwrap();
+                }
             }
         }
+        writeln!(out).unwrap();
+    }
+    out
+}
+
+fn memcheck_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let prog = load(opts);
+
+    for error in memcheck(&prog)
+        .unwrap_or_else(fail)
+        .iter()
+        .filter(|error| opts.functions.is_empty() || opts.functions.contains(&error.func))
+    {
+        writeln!(out, "{}", error).unwrap();
+    }
+    out
+}
+
+fn dfe_mode(opts: &Options) -> String {
+    let roots = if opts.roots.is_empty() {
+        vec![String::from("main")]
+    } else {
+        opts.roots.clone()
+    };
+    let (new_prog, removed) = dead_function_elim(&load(opts), &roots).unwrap_or_else(fail);
+    for name in removed {
+        eprintln!("removed @{}", name);
+    }
+    format_program(&new_prog, opts)
+}
+
+fn interp_mode(opts: &Options) -> String {
+    let mut out = String::new();
+    let execution = interpret(&load(opts), &opts.args).unwrap_or_else(fail);
+    if let Some(err) = execution.failure {
+        fail(err);
+    }
+    for line in execution.output.iter() {
+        writeln!(out, "{}", line).unwrap();
     }
+    eprintln!("total_dyn_inst: {}", execution.instructions);
+    out
+}
+
+// the program the fuzzer generates from the seed
+fn generate_mode(opts: &Options) -> String {
+    format_program(&random_program(opts.seed), opts)
 }
@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Type, ValueOps};
+
+use crate::{
+    alias::{AliasAnalysis, Location},
+    analysis_manager::AnalysisManager,
+    analyze::Definition,
+    error::Result,
+    ssa::{convert_vars_to_ssa, defined_vars},
+};
+
+// number of leading arguments of `instr` that may be pointers to a promoted allocation
+fn promotable_args(instr: &Instruction) -> usize {
+    match instr {
+        Instruction::Value {
+            args,
+            op: ValueOps::Id | ValueOps::Phi,
+            ..
+        } => args.len(),
+        Instruction::Value {
+            op: ValueOps::PtrAdd | ValueOps::Load,
+            ..
+        }
+        | Instruction::Effect {
+            op: EffectOps::Store | EffectOps::Free,
+            ..
+        } => 1,
+        _ => 0,
+    }
+}
+
+// Replaces the loads and stores of allocations that never escape and are only accessed at
+// constant offsets with variables in SSA form, then deletes their `alloc` and `free`.
+pub fn scalar_replacement(func: &Function, analyses: &mut AnalysisManager) -> Result<Function> {
+    let alias = AliasAnalysis::new(func, analyses)?;
+    let taken_var_names = defined_vars(func);
+
+    // pointer variables that always point at the same cell of a single allocation
+    let mut cells: HashMap<String, (Definition, i64)> = HashMap::new();
+    let mut rejected: HashSet<Definition> = HashSet::new();
+    for var in taken_var_names.iter() {
+        let locs: Vec<Location> = alias.points_to(var).into_iter().collect();
+        match &locs[..] {
+            [Location::Site(site, Some(offset))] => {
+                cells.insert(var.clone(), (site.clone(), *offset));
+            }
+            _ => rejected.extend(locs.iter().filter_map(|loc| match loc {
+                Location::Site(site, _) => Some(site.clone()),
+                Location::Unknown => None,
+            })),
+        }
+    }
+
+    // allocations escape through any other use of their pointers, e.g. by being stored
+    for code in func.instrs.iter() {
+        if let Code::Instruction(instr) = code {
+            let allowed = promotable_args(instr);
+            if let Instruction::Value { args, .. } | Instruction::Effect { args, .. } = instr {
+                for arg in args.iter().skip(allowed) {
+                    if let Some((site, _)) = cells.get(arg) {
+                        rejected.insert(site.clone());
+                    }
+                }
+            }
+        }
+    }
+    cells.retain(|_, cell| !rejected.contains(&cell.0));
+    if cells.is_empty() {
+        return Ok(func.clone());
+    }
+
+    // one fresh variable per promoted cell, typed like the allocation's elements
+    let site_types: HashMap<String, Type> = func
+        .instrs
+        .iter()
+        .filter_map(|code| match code {
+            Code::Instruction(Instruction::Value {
+                dest,
+                op: ValueOps::Alloc,
+                op_type: Type::Pointer(elem_type),
+                ..
+            }) => Some((dest.clone(), (**elem_type).clone())),
+            _ => None,
+        })
+        .collect();
+    let mut cell_names: HashMap<(Definition, i64), String> = HashMap::new();
+    for (site, offset) in cells.values() {
+        if cell_names.contains_key(&(site.clone(), *offset)) {
+            continue;
+        }
+        let mut name = format!("{}_{}", site.name, offset);
+        while taken_var_names.contains(&name) || cell_names.values().any(|n| n == &name) {
+            name = name + "_";
+        }
+        cell_names.insert((site.clone(), *offset), name);
+    }
+    let cell_of = |ptr: &String| -> (String, Type) {
+        let (site, offset) = &cells[ptr];
+        (
+            cell_names[&(site.clone(), *offset)].clone(),
+            site_types[&site.name].clone(),
+        )
+    };
+
+    let instrs = func
+        .instrs
+        .iter()
+        .filter_map(|code| match code {
+            // pointer arithmetic, copies and the allocation itself are no longer needed
+            Code::Instruction(Instruction::Value { dest, .. }) if cells.contains_key(dest) => None,
+            Code::Instruction(Instruction::Effect {
+                args,
+                op: EffectOps::Free,
+                ..
+            }) if cells.contains_key(&args[0]) => None,
+            Code::Instruction(Instruction::Value {
+                args,
+                dest,
+                op: ValueOps::Load,
+                pos,
+                op_type,
+                ..
+            }) if cells.contains_key(&args[0]) => Some(Code::Instruction(Instruction::Value {
+                args: vec![cell_of(&args[0]).0],
+                dest: dest.clone(),
+                funcs: vec![],
+                labels: vec![],
+                op: ValueOps::Id,
+                pos: pos.clone(),
+                op_type: op_type.clone(),
+            })),
+            Code::Instruction(Instruction::Effect {
+                args,
+                op: EffectOps::Store,
+                pos,
+                ..
+            }) if cells.contains_key(&args[0]) => {
+                let (cell, cell_type) = cell_of(&args[0]);
+                Some(Code::Instruction(Instruction::Value {
+                    args: vec![args[1].clone()],
+                    dest: cell,
+                    funcs: vec![],
+                    labels: vec![],
+                    op: ValueOps::Id,
+                    pos: pos.clone(),
+                    op_type: cell_type,
+                }))
+            }
+            _ => Some(code.clone()),
+        })
+        .collect();
+
+    let promoted = Function {
+        args: func.args.clone(),
+        instrs,
+        name: func.name.clone(),
+        pos: func.pos.clone(),
+        return_type: func.return_type.clone(),
+    };
+    // deleting instructions can empty a block and shift the names of the ones after it
+    analyses.invalidate(&promoted.name, &[]);
+    convert_vars_to_ssa(&promoted, &cell_names.into_values().collect(), analyses)
+}
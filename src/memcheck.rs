@@ -0,0 +1,434 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Position, Program, Type, ValueOps};
+
+use crate::{
+    alias::{AliasAnalysis, Location},
+    analysis_manager::AnalysisManager,
+    analyze::Definition,
+    error::Result,
+    parse::{block_name_to_idx, expanded_basic_blocks, get_block_name, BasicBlock},
+    util::{invert_digraph, position_string},
+};
+
+// what may have happened to an allocation site along the paths reaching a program point
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
+enum Status {
+    Unallocated,
+    Allocated,
+    Freed,
+    // returned, stored to memory, or passed to a function that frees memory or returns a pointer
+    Escaped,
+}
+
+// None if the program point is unreachable
+type State = Option<HashMap<Definition, HashSet<Status>>>;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MemoryErrorKind {
+    Leak,
+    DoubleFree,
+    UseAfterFree,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryError {
+    pub kind: MemoryErrorKind,
+    pub func: String,
+    pub var: String,
+    pub pos: Option<Position>,
+    pub definite: bool, // false if the error only happens along some paths
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let certainty = if self.definite { "" } else { "possible " };
+        let what = match self.kind {
+            MemoryErrorKind::Leak => "memory leak of",
+            MemoryErrorKind::DoubleFree => "double free of",
+            MemoryErrorKind::UseAfterFree => "use after free of",
+        };
+        write!(
+            f,
+            "{}: in @{}: {}{} `{}`",
+            position_string(&self.pos),
+            self.func,
+            certainty,
+            what,
+            self.var
+        )
+    }
+}
+
+// functions that may free memory, directly or through their callees
+pub fn freeing_functions(prog: &Program) -> HashSet<String> {
+    let mut freeing: HashSet<String> = HashSet::new();
+    loop {
+        let next: HashSet<String> = prog
+            .functions
+            .iter()
+            .filter(|func| {
+                func.instrs.iter().any(|code| match code {
+                    Code::Instruction(Instruction::Effect {
+                        op: EffectOps::Free,
+                        ..
+                    }) => true,
+                    Code::Instruction(Instruction::Effect {
+                        op: EffectOps::Call,
+                        funcs,
+                        ..
+                    })
+                    | Code::Instruction(Instruction::Value {
+                        op: ValueOps::Call,
+                        funcs,
+                        ..
+                    }) => funcs.iter().any(|callee| freeing.contains(callee)),
+                    _ => false,
+                })
+            })
+            .map(|func| func.name.clone())
+            .collect();
+        if next == freeing {
+            break;
+        }
+        freeing = next;
+    }
+    freeing
+}
+
+pub fn memcheck(prog: &Program) -> Result<Vec<MemoryError>> {
+    let freeing = freeing_functions(prog);
+    let mut analyses = AnalysisManager::new();
+    let mut reports = vec![];
+    for func in prog.functions.iter() {
+        reports.extend(memcheck_function(func, &freeing, &mut analyses)?);
+    }
+    Ok(reports)
+}
+
+// Reports leaks at `ret` and at the end of the function, double frees, and loads or stores
+// through freed pointers. Callees are assumed to free nothing unless they are in `freeing`, and
+// allocations passed to a callee returning a pointer are no longer tracked.
+pub fn memcheck_function(
+    func: &Function,
+    freeing: &HashSet<String>,
+    analyses: &mut AnalysisManager,
+) -> Result<Vec<MemoryError>> {
+    let checker = Checker::new(func, freeing, analyses)?;
+    let successors = analyses.control_flow_graph(func)?;
+    let predecessors = invert_digraph(&successors);
+
+    let mut inputs: HashMap<String, State> = HashMap::new();
+    let mut outputs: HashMap<String, State> =
+        successors.keys().map(|b| (b.clone(), None)).collect();
+
+    let mut worklist: Vec<String> = successors.keys().cloned().collect();
+    while let Some(b) = worklist.pop() {
+        // merge
+        let input = if b == "entry" {
+            Some(
+                checker
+                    .sites
+                    .iter()
+                    .map(|site| (site.clone(), HashSet::from([Status::Unallocated])))
+                    .collect(),
+            )
+        } else {
+            predecessors[&b]
+                .iter()
+                .fold(None, |acc, p| join(acc, &outputs[p]))
+        };
+
+        // transfer
+        let output = checker.transfer(&b, &input, &mut vec![]);
+        inputs.insert(b.clone(), input);
+        if output != outputs[&b] {
+            worklist.append(&mut successors[&b].clone());
+            outputs.insert(b, output);
+        }
+    }
+
+    // report errors using the converged states, in program order
+    let mut reports = vec![];
+    for (idx, block) in checker.blocks.iter().enumerate() {
+        let name = get_block_name(block, idx, &func.name);
+        checker.transfer(&name, &inputs[&name], &mut reports);
+    }
+    if let Some(Some(state)) = inputs.get("exit") {
+        checker.check_leaks(state, &mut reports);
+    }
+    Ok(reports)
+}
+
+fn join(acc: State, other: &State) -> State {
+    match (acc, other) {
+        (None, other) => other.clone(),
+        (acc, None) => acc,
+        (Some(mut acc), Some(other)) => {
+            for (site, statuses) in other {
+                acc.entry(site.clone()).or_default().extend(statuses);
+            }
+            Some(acc)
+        }
+    }
+}
+
+struct Checker<'a> {
+    func: &'a Function,
+    freeing: &'a HashSet<String>,
+    blocks: Vec<BasicBlock>,
+    block_map: HashMap<String, usize>,
+    alias: AliasAnalysis,
+    sites: Vec<Definition>, // allocation sites in program order
+}
+
+impl<'a> Checker<'a> {
+    fn new(
+        func: &'a Function,
+        freeing: &'a HashSet<String>,
+        analyses: &mut AnalysisManager,
+    ) -> Result<Checker<'a>> {
+        let blocks = expanded_basic_blocks(func);
+        let sites = blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, block)| {
+                let block_name = get_block_name(block, idx, &func.name);
+                block
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(line, code)| match code {
+                        Code::Instruction(Instruction::Value {
+                            dest,
+                            op: ValueOps::Alloc,
+                            ..
+                        }) => Some(Definition {
+                            name: dest.clone(),
+                            block: block_name.clone(),
+                            line,
+                        }),
+                        _ => None,
+                    })
+            })
+            .collect();
+
+        Ok(Checker {
+            func,
+            freeing,
+            block_map: block_name_to_idx(func),
+            alias: AliasAnalysis::new(func, analyses)?,
+            blocks,
+            sites,
+        })
+    }
+
+    // allocation sites `var` may point into before the instruction at `line` of `block`, and
+    // whether it may also point elsewhere
+    fn sites_of(&self, var: &String, block: &String, line: usize) -> (Vec<Definition>, bool) {
+        let mut sites = vec![];
+        let mut unknown = false;
+        for loc in self.alias.points_to_at(var, block, line) {
+            match loc {
+                Location::Site(site, _) => {
+                    if !sites.contains(&site) {
+                        sites.push(site);
+                    }
+                }
+                Location::Unknown => unknown = true,
+            }
+        }
+        (sites, unknown)
+    }
+
+    fn site_pos(&self, site: &Definition) -> Option<Position> {
+        match &self.blocks[self.block_map[&site.block]][site.line] {
+            Code::Instruction(Instruction::Value { pos, .. }) => pos.clone(),
+            _ => None,
+        }
+    }
+
+    fn report(&self, reports: &mut Vec<MemoryError>, error: MemoryError) {
+        if !reports.contains(&error) {
+            reports.push(error);
+        }
+    }
+
+    fn escape(
+        &self,
+        state: &mut HashMap<Definition, HashSet<Status>>,
+        vars: &[String],
+        block: &String,
+        line: usize,
+    ) {
+        for var in vars {
+            for site in self.sites_of(var, block, line).0 {
+                state.entry(site).or_default().insert(Status::Escaped);
+            }
+        }
+    }
+
+    fn check_leak(
+        &self,
+        site: &Definition,
+        statuses: &HashSet<Status>,
+        reports: &mut Vec<MemoryError>,
+    ) {
+        if statuses.contains(&Status::Allocated) && !statuses.contains(&Status::Escaped) {
+            self.report(
+                reports,
+                MemoryError {
+                    kind: MemoryErrorKind::Leak,
+                    func: self.func.name.clone(),
+                    var: site.name.clone(),
+                    pos: self.site_pos(site),
+                    definite: statuses.len() == 1,
+                },
+            );
+        }
+    }
+
+    fn check_leaks(
+        &self,
+        state: &HashMap<Definition, HashSet<Status>>,
+        reports: &mut Vec<MemoryError>,
+    ) {
+        for site in self.sites.iter() {
+            self.check_leak(site, &state[site], reports);
+        }
+    }
+
+    fn check_freed(
+        &self,
+        state: &HashMap<Definition, HashSet<Status>>,
+        kind: MemoryErrorKind,
+        var: &String,
+        sites: &[Definition],
+        pos: &Option<Position>,
+        reports: &mut Vec<MemoryError>,
+    ) {
+        for site in sites {
+            let statuses = &state[site];
+            if statuses.contains(&Status::Freed) && !statuses.contains(&Status::Escaped) {
+                self.report(
+                    reports,
+                    MemoryError {
+                        kind,
+                        func: self.func.name.clone(),
+                        var: var.clone(),
+                        pos: pos.clone(),
+                        definite: statuses.len() == 1,
+                    },
+                );
+            }
+        }
+    }
+
+    fn transfer(
+        &self,
+        block_name: &String,
+        input: &State,
+        reports: &mut Vec<MemoryError>,
+    ) -> State {
+        let mut state = input.clone()?;
+        let block = &self.blocks[self.block_map[block_name]];
+
+        for (line, code) in block.iter().enumerate() {
+            let Code::Instruction(instr) = code else {
+                continue;
+            };
+            match instr {
+                Instruction::Value {
+                    dest,
+                    op: ValueOps::Alloc,
+                    ..
+                } => {
+                    let site = Definition {
+                        name: dest.clone(),
+                        block: block_name.clone(),
+                        line,
+                    };
+                    // the previous allocation from this site is lost
+                    self.check_leak(&site, &state[&site], reports);
+                    state.insert(site, HashSet::from([Status::Allocated]));
+                }
+                Instruction::Value {
+                    args,
+                    op: ValueOps::Load,
+                    pos,
+                    ..
+                } => {
+                    let kind = MemoryErrorKind::UseAfterFree;
+                    let sites = self.sites_of(&args[0], block_name, line).0;
+                    self.check_freed(&state, kind, &args[0], &sites, pos, reports);
+                }
+                Instruction::Effect {
+                    args,
+                    op: EffectOps::Store,
+                    pos,
+                    ..
+                } => {
+                    let kind = MemoryErrorKind::UseAfterFree;
+                    let sites = self.sites_of(&args[0], block_name, line).0;
+                    self.check_freed(&state, kind, &args[0], &sites, pos, reports);
+                    self.escape(&mut state, &args[1..], block_name, line);
+                }
+                Instruction::Effect {
+                    args,
+                    op: EffectOps::Free,
+                    pos,
+                    ..
+                } => {
+                    let kind = MemoryErrorKind::DoubleFree;
+                    let (sites, unknown) = self.sites_of(&args[0], block_name, line);
+                    self.check_freed(&state, kind, &args[0], &sites, pos, reports);
+                    if sites.len() == 1 && !unknown {
+                        state.insert(sites[0].clone(), HashSet::from([Status::Freed]));
+                    } else {
+                        for site in sites {
+                            state.entry(site).or_default().insert(Status::Freed);
+                        }
+                    }
+                }
+                Instruction::Effect {
+                    args,
+                    funcs,
+                    op: EffectOps::Call,
+                    ..
+                }
+                | Instruction::Value {
+                    args,
+                    funcs,
+                    op: ValueOps::Call,
+                    ..
+                } => {
+                    // the returned pointer may be into an argument's allocation, which frees
+                    // through it cannot be traced back to
+                    let returns_pointer = matches!(
+                        instr,
+                        Instruction::Value {
+                            op_type: Type::Pointer(_),
+                            ..
+                        }
+                    );
+                    if returns_pointer || funcs.iter().any(|callee| self.freeing.contains(callee)) {
+                        self.escape(&mut state, args, block_name, line);
+                    }
+                }
+                Instruction::Effect {
+                    args,
+                    op: EffectOps::Return,
+                    ..
+                } => {
+                    self.escape(&mut state, args, block_name, line);
+                    self.check_leaks(&state, reports);
+                    return None;
+                }
+                _ => {}
+            }
+        }
+        Some(state)
+    }
+}
@@ -1,11 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use bril_rs::{Code, Function, Instruction};
+use bril_rs::{Code, EffectOps, Function, Instruction, ValueOps};
 
+use crate::alias::AliasAnalysis;
+use crate::analysis_manager::AnalysisManager;
+use crate::error::Result;
 use crate::lvn::LVN;
-use crate::parse::BasicBlock;
+use crate::parse::{block_name_to_idx, expanded_basic_blocks, get_block_name, BasicBlock};
+use crate::summary::{is_pure_call, Summaries};
+use crate::util::invert_digraph;
 
-pub fn dead_variable_elim(f: &Function) -> Function {
+// Removes definitions that are never used. Calls stay unless the callee is pure, since the
+// callee may have effects even when its result is unused.
+pub fn dead_variable_elim(f: &Function, summaries: &Summaries) -> Result<Function> {
     let mut last = f.clone();
     loop {
         let used_vars: Vec<String> = last
@@ -30,6 +37,17 @@ pub fn dead_variable_elim(f: &Function) -> Function {
                 .iter()
                 .filter(|&x| -> bool {
                     match &x {
+                        Code::Instruction(Instruction::Value {
+                            op: ValueOps::Call, ..
+                        })
+                        | Code::Instruction(Instruction::Effect {
+                            op: EffectOps::Call,
+                            ..
+                        }) if !is_pure_call(x, summaries) => true,
+                        Code::Instruction(Instruction::Effect {
+                            op: EffectOps::Call,
+                            ..
+                        }) => false,
                         Code::Instruction(Instruction::Constant { dest, .. })
                         | Code::Instruction(Instruction::Value { dest, .. }) => {
                             return if used_vars.contains(&dest) {
@@ -50,58 +68,241 @@ pub fn dead_variable_elim(f: &Function) -> Function {
         }
         last = func;
     }
-    return last;
+    Ok(last)
 }
 
-pub fn dead_store_elim(b: &BasicBlock) -> BasicBlock {
-    let mut last = b.clone();
-    loop {
-        let (block, _) = last.iter().enumerate().fold(
-            (last.clone(), HashMap::new()),
-            |(mut block, mut unused_defs), (i, instr)| {
-                // Check for variable uses
-                if let Code::Instruction(Instruction::Value { args, .. })
-                | Code::Instruction(Instruction::Effect { args, .. }) = instr
-                {
-                    for var in args.iter() {
-                        if unused_defs.contains_key(&var) {
-                            unused_defs.remove(var);
-                        }
-                    }
-                }
-                // Check for variable definitions
-                if let Code::Instruction(Instruction::Constant { dest, .. })
-                | Code::Instruction(Instruction::Value { dest, .. }) = instr
-                {
-                    if unused_defs.contains_key(dest) {
-                        block.remove(unused_defs[dest]);
-                    }
-                    unused_defs.insert(dest, i);
-                }
-                // Return
-                (block, unused_defs)
-            },
-        );
+// calls and allocations have to run even if their result is overwritten
+fn has_side_effects(instr: &Code) -> bool {
+    matches!(
+        instr,
+        Code::Instruction(Instruction::Value {
+            op: ValueOps::Call | ValueOps::Alloc,
+            ..
+        })
+    )
+}
 
-        if block == last {
-            break;
+// removes definitions that are overwritten later in the block before being used
+pub fn dead_store_elim(b: &BasicBlock) -> BasicBlock {
+    // walk backwards so that the uses in removed definitions do not keep other definitions alive,
+    // then compact the block once
+    let mut overwritten: HashSet<&String> = HashSet::new();
+    let mut dead = vec![false; b.len()];
+    for (i, instr) in b.iter().enumerate().rev() {
+        if let Code::Instruction(Instruction::Constant { dest, .. })
+        | Code::Instruction(Instruction::Value { dest, .. }) = instr
+        {
+            if overwritten.contains(dest) && !has_side_effects(instr) {
+                dead[i] = true;
+                continue;
+            }
+            overwritten.insert(dest);
+        }
+        if let Code::Instruction(Instruction::Value { args, .. })
+        | Code::Instruction(Instruction::Effect { args, .. }) = instr
+        {
+            for var in args.iter() {
+                overwritten.remove(var);
+            }
         }
-        last = block;
     }
-    return last;
+
+    b.iter()
+        .zip(dead)
+        .filter(|(_, dead)| !dead)
+        .map(|(instr, _)| instr.clone())
+        .collect()
 }
 
-pub fn lvn_block(block: &BasicBlock, folding: bool) -> BasicBlock {
-    let mut lvn = LVN::new(folding);
+// `func` is the name of the function the block is from, for errors
+pub fn lvn_block(
+    func: &String,
+    block: &BasicBlock,
+    folding: bool,
+    summaries: &Summaries,
+) -> Result<BasicBlock> {
+    let mut lvn = LVN::new(func, folding, summaries);
 
     for variable in lvn.read_first(block) {
         let num = lvn.register_var(&variable);
         lvn.register_dest(&variable, num, true);
     }
 
-    return block
+    block
         .iter()
         .zip(LVN::last_writes(block).iter())
-        .map(|(instr, last_write)| -> Code { lvn.optimize_instruction(instr, *last_write) })
+        .map(|(instr, last_write)| lvn.optimize_instruction(instr, *last_write))
+        .collect()
+}
+
+// memory that is known to be overwritten or freed before it can be read again
+#[derive(Debug, Eq, Hash, PartialEq, Clone)]
+enum DeadMemory {
+    Stored(String), // the cell the pointer variable currently points to
+    Freed(String),  // the whole allocation the pointer variable currently points into
+}
+
+impl DeadMemory {
+    fn var(&self) -> &String {
+        match self {
+            DeadMemory::Stored(var) | DeadMemory::Freed(var) => var,
+        }
+    }
+}
+
+// Walks `block` backwards from `output`, the memory that is dead after it, and returns the memory
+// that is dead before it. Lines of stores that are dead are pushed to `dead_stores`.
+fn dead_memory_transfer(
+    alias: &AliasAnalysis,
+    block_name: &String,
+    block: &BasicBlock,
+    output: &HashSet<DeadMemory>,
+    dead_stores: &mut Vec<usize>,
+) -> HashSet<DeadMemory> {
+    let mut dead = output.clone();
+    for (line, code) in block.iter().enumerate().rev() {
+        let Code::Instruction(instr) = code else {
+            continue;
+        };
+
+        // the facts were about the value a redefined variable has afterwards
+        if let Instruction::Constant { dest, .. } | Instruction::Value { dest, .. } = instr {
+            dead.retain(|fact| fact.var() != dest);
+        }
+
+        match instr {
+            Instruction::Effect {
+                args,
+                op: EffectOps::Store,
+                ..
+            } => {
+                let overwritten = dead.iter().any(|fact| match fact {
+                    DeadMemory::Stored(ptr) => alias.must_alias(&args[0], ptr, block_name, line),
+                    DeadMemory::Freed(ptr) => {
+                        alias.must_alias_allocation(&args[0], ptr, block_name, line)
+                    }
+                });
+                if overwritten {
+                    dead_stores.push(line);
+                }
+                dead.insert(DeadMemory::Stored(args[0].clone()));
+            }
+            Instruction::Effect {
+                args,
+                op: EffectOps::Free,
+                ..
+            } => {
+                dead.insert(DeadMemory::Freed(args[0].clone()));
+            }
+            Instruction::Value {
+                args,
+                op: ValueOps::Load,
+                ..
+            } => dead.retain(|fact| match fact {
+                DeadMemory::Stored(ptr) => !alias.may_alias(&args[0], ptr, block_name, line),
+                DeadMemory::Freed(ptr) => {
+                    !alias.may_alias_allocation(&args[0], ptr, block_name, line)
+                }
+            }),
+            // callees and callers may read any memory, and speculation may be rolled back
+            Instruction::Value {
+                op: ValueOps::Call, ..
+            }
+            | Instruction::Effect {
+                op:
+                    EffectOps::Call
+                    | EffectOps::Return
+                    | EffectOps::Speculate
+                    | EffectOps::Commit
+                    | EffectOps::Guard,
+                ..
+            } => dead.clear(),
+            _ => {}
+        }
+    }
+    dead
+}
+
+// Removes stores whose value is overwritten by a store to a must-aliasing pointer, or whose
+// allocation is freed, on every path before any possibly aliasing load.
+pub fn dead_memory_store_elim(func: &Function, analyses: &mut AnalysisManager) -> Result<Function> {
+    let alias = AliasAnalysis::new(func, analyses)?;
+    let blocks = expanded_basic_blocks(func);
+    let block_map = block_name_to_idx(func);
+    let successors = analyses.control_flow_graph(func)?;
+    let predecessors = invert_digraph(&successors);
+
+    // blocks from which the end of the function can be reached
+    let mut reaches_exit: HashSet<&String> = successors
+        .iter()
+        .filter(|(_, succs)| succs.is_empty())
+        .map(|(b, _)| b)
         .collect();
+    let mut stack: Vec<&String> = reaches_exit.iter().copied().collect();
+    while let Some(b) = stack.pop() {
+        for p in predecessors[b].iter() {
+            if reaches_exit.insert(p) {
+                stack.push(p);
+            }
+        }
+    }
+
+    // backward must-analysis, None means no path to the end of the function was seen yet. Blocks
+    // that never reach the end, like infinite loops, would stay None, so nothing is dead there.
+    let mut inputs: HashMap<String, Option<HashSet<DeadMemory>>> =
+        successors.keys().map(|b| (b.clone(), None)).collect();
+    let merge = |inputs: &HashMap<String, Option<HashSet<DeadMemory>>>, b: &String| {
+        successors[b]
+            .iter()
+            .filter_map(|s| {
+                if reaches_exit.contains(s) {
+                    inputs[s].clone()
+                } else {
+                    Some(HashSet::new())
+                }
+            })
+            .reduce(|acc, input| acc.intersection(&input).cloned().collect())
+            .or_else(|| successors[b].is_empty().then(HashSet::new))
+    };
+
+    let mut worklist: Vec<String> = successors.keys().cloned().collect();
+    while let Some(b) = worklist.pop() {
+        let Some(output) = merge(&inputs, &b) else {
+            continue;
+        };
+        let block = &blocks[block_map[&b]];
+        let input = dead_memory_transfer(&alias, &b, block, &output, &mut vec![]);
+        if Some(&input) != inputs[&b].as_ref() {
+            worklist.append(&mut predecessors[&b].clone());
+            inputs.insert(b, Some(input));
+        }
+    }
+
+    let mut dead_stores: HashSet<(usize, usize)> = HashSet::new();
+    for (idx, block) in blocks.iter().enumerate() {
+        let b = get_block_name(block, idx, &func.name);
+        let output = merge(&inputs, &b).unwrap_or_default();
+        let mut lines = vec![];
+        dead_memory_transfer(&alias, &b, block, &output, &mut lines);
+        dead_stores.extend(lines.into_iter().map(|line| (idx, line)));
+    }
+
+    Ok(Function {
+        args: func.args.clone(),
+        instrs: blocks[1..blocks.len() - 1]
+            .iter()
+            .enumerate()
+            .flat_map(|(i, block)| {
+                let dead_stores = &dead_stores;
+                block
+                    .iter()
+                    .enumerate()
+                    .filter(move |(line, _)| !dead_stores.contains(&(i + 1, *line)))
+                    .map(|(_, code)| code.clone())
+            })
+            .collect(),
+        name: func.name.clone(),
+        pos: func.pos.clone(),
+        return_type: func.return_type.clone(),
+    })
 }
@@ -1,7 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use bril_rs::{Code, EffectOps, Function, Instruction};
 
+use crate::error::{Error, Result};
+
 pub type ControlFlowGraph = HashMap<String, Vec<String>>;
 pub type BasicBlock = Vec<Code>;
 
@@ -73,11 +75,24 @@ pub fn block_name_to_idx(func: &Function) -> HashMap<String, usize> {
         .collect()
 }
 
-pub fn control_flow_graph(func: &Function) -> ControlFlowGraph {
+// fails if a label is defined twice or a jump or branch targets an undefined label
+pub fn control_flow_graph(func: &Function) -> Result<ControlFlowGraph> {
     let fname = &func.name;
     let mut cfg = ControlFlowGraph::new();
     let blocks = expanded_basic_blocks(&func);
-    assert!(blocks.len() > 0);
+
+    let mut defined_labels: HashSet<&String> = HashSet::new();
+    for code in func.instrs.iter() {
+        if let Code::Label { label, pos } = code {
+            if !defined_labels.insert(label) {
+                return Err(Error::Malformed {
+                    func: fname.clone(),
+                    msg: format!("label .{} is defined more than once", label),
+                    pos: pos.clone(),
+                });
+            }
+        }
+    }
 
     for i in 0..blocks.len() - 1 {
         let block = &blocks[i];
@@ -86,10 +101,19 @@ pub fn control_flow_graph(func: &Function) -> ControlFlowGraph {
 
         match &last {
             Code::Instruction(instr) => match &instr {
-                Instruction::Effect { op, labels, .. }
-                    if op == &EffectOps::Jump || op == &EffectOps::Branch =>
-                {
+                Instruction::Effect {
+                    op, labels, pos, ..
+                } if op == &EffectOps::Jump || op == &EffectOps::Branch => {
                     // Last instruction in block is a jump or branch
+                    if let Some(label) =
+                        labels.iter().find(|&label| !defined_labels.contains(label))
+                    {
+                        return Err(Error::UnknownLabel {
+                            func: fname.clone(),
+                            label: label.clone(),
+                            pos: pos.clone(),
+                        });
+                    }
                     cfg.insert(from, labels.clone());
                 }
                 _ => {
@@ -107,5 +131,5 @@ pub fn control_flow_graph(func: &Function) -> ControlFlowGraph {
         vec![],
     );
 
-    return cfg;
+    return Ok(cfg);
 }
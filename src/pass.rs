@@ -0,0 +1,368 @@
+use std::{cell::RefCell, collections::HashMap, io::Write, iter::Peekable, str::Chars};
+
+use bril_rs::{Function, Program};
+
+use crate::{
+    analysis_manager::{Analysis, AnalysisManager, CONTROL_FLOW_ANALYSES},
+    dfe::DeadFunctionElim,
+    error::{Error, Result},
+    inline::{Inliner, DEFAULT_INLINE_THRESHOLD},
+    mem2reg::scalar_replacement,
+    optimize::{dead_memory_store_elim, dead_store_elim, dead_variable_elim, lvn_block},
+    parse::{basic_blocks, BasicBlock},
+    reduce::{crash_on_calls, delete_calls},
+    specialize::{Specializer, DEFAULT_SPECIALIZE_BUDGET},
+    ssa::{convert_vars_to_ssa, defined_vars},
+    strength::strength_reduction,
+    tce::tail_call_elim,
+    unroll::{Unroller, DEFAULT_UNROLL_FACTOR},
+    verify::verify_program,
+};
+
+// in case the passes of a fixed-point group keep undoing each other
+const MAX_ITERATIONS: usize = 100;
+
+// names accepted by `create_pass`
+pub const PASSES: [&str; 13] = [
+    "lvn",
+    "fold",
+    "dce",
+    "dse",
+    "ssa",
+    "mem2reg",
+    "memdse",
+    "inline",
+    "dfe",
+    "specialize",
+    "tce",
+    "unroll",
+    "sr",
+];
+
+pub trait Pass {
+    fn name(&self) -> &str;
+
+    // analyses that stay valid for every function the pass changes
+    fn preserved_analyses(&self) -> &[Analysis] {
+        &[]
+    }
+
+    // function-level passes only override this
+    fn run_on_function(
+        &self,
+        func: &Function,
+        _analyses: &mut AnalysisManager,
+    ) -> Result<Function> {
+        Ok(func.clone())
+    }
+
+    // program-level passes override this to see every function at once
+    fn run_on_program(&self, prog: &Program, analyses: &mut AnalysisManager) -> Result<Program> {
+        let mut new_prog = prog.clone();
+        new_prog.functions = prog
+            .functions
+            .iter()
+            .map(|func| self.run_on_function(func, analyses))
+            .collect::<Result<_>>()?;
+        Ok(new_prog)
+    }
+}
+
+// a pass that transforms each function on its own
+pub struct FunctionPass {
+    name: String,
+    transform: fn(&Function, &mut AnalysisManager) -> Result<Function>,
+    preserved: &'static [Analysis],
+}
+
+impl FunctionPass {
+    pub fn new(
+        name: &str,
+        transform: fn(&Function, &mut AnalysisManager) -> Result<Function>,
+        preserved: &'static [Analysis],
+    ) -> FunctionPass {
+        FunctionPass {
+            name: String::from(name),
+            transform,
+            preserved,
+        }
+    }
+}
+
+impl Pass for FunctionPass {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn preserved_analyses(&self) -> &[Analysis] {
+        self.preserved
+    }
+
+    fn run_on_function(&self, func: &Function, analyses: &mut AnalysisManager) -> Result<Function> {
+        (self.transform)(func, analyses)
+    }
+}
+
+// rebuilds the function from its transformed basic blocks
+pub fn map_blocks(
+    func: &Function,
+    transform: impl Fn(&BasicBlock) -> Result<BasicBlock>,
+) -> Result<Function> {
+    let mut instrs = vec![];
+    for block in basic_blocks(func).iter() {
+        instrs.extend(transform(block)?);
+    }
+    Ok(Function {
+        args: func.args.clone(),
+        instrs,
+        name: func.name.clone(),
+        pos: func.pos.clone(),
+        return_type: func.return_type.clone(),
+    })
+}
+
+// `inline=N` is the inliner with a size threshold of N instructions, `dfe=f+g` removes the
+// functions that cannot be reached from @f or @g instead of from @main, `specialize=N` adds at
+// most N instructions of specialized functions, and `unroll=N` partially unrolls by a factor of N
+pub fn create_pass(name: &str) -> Option<Box<dyn Pass>> {
+    if name == "inline" {
+        return Some(Box::new(Inliner::new(DEFAULT_INLINE_THRESHOLD)));
+    }
+    if let Some(threshold) = name.strip_prefix("inline=") {
+        return Some(Box::new(Inliner::new(threshold.parse().ok()?)));
+    }
+    if name == "specialize" {
+        return Some(Box::new(Specializer::new(DEFAULT_SPECIALIZE_BUDGET)));
+    }
+    if let Some(budget) = name.strip_prefix("specialize=") {
+        return Some(Box::new(Specializer::new(budget.parse().ok()?)));
+    }
+    if name == "unroll" {
+        return Some(Box::new(Unroller::new(DEFAULT_UNROLL_FACTOR)));
+    }
+    if let Some(factor) = name.strip_prefix("unroll=") {
+        return Some(Box::new(Unroller::new(factor.parse().ok()?)));
+    }
+    if name == "dfe" {
+        return Some(Box::new(DeadFunctionElim::new(vec![String::from("main")])));
+    }
+    if let Some(roots) = name.strip_prefix("dfe=") {
+        let roots = roots
+            .split('+')
+            .map(|root| String::from(root.trim_start_matches('@')));
+        return Some(Box::new(DeadFunctionElim::new(roots.collect())));
+    }
+    // passes that only rewrite instructions in place keep the control flow analyses, passes that
+    // delete instructions may empty a block and so rename the blocks after it
+    let (transform, preserved): (
+        fn(&Function, &mut AnalysisManager) -> Result<Function>,
+        &'static [Analysis],
+    ) = match name {
+        "lvn" => (
+            |func, analyses| {
+                let summaries = analyses.function_summaries()?;
+                map_blocks(func, |block| {
+                    lvn_block(&func.name, block, false, &summaries)
+                })
+            },
+            CONTROL_FLOW_ANALYSES,
+        ),
+        "fold" => (
+            |func, analyses| {
+                let summaries = analyses.function_summaries()?;
+                map_blocks(func, |block| lvn_block(&func.name, block, true, &summaries))
+            },
+            CONTROL_FLOW_ANALYSES,
+        ),
+        "dce" => (
+            |func, analyses| dead_variable_elim(func, &analyses.function_summaries()?),
+            &[],
+        ),
+        "dse" => (
+            |func, _| map_blocks(func, |block| Ok(dead_store_elim(block))),
+            &[],
+        ),
+        "ssa" => (
+            |func, analyses| convert_vars_to_ssa(func, &defined_vars(func), analyses),
+            CONTROL_FLOW_ANALYSES,
+        ),
+        "mem2reg" => (scalar_replacement, &[]),
+        "memdse" => (dead_memory_store_elim, &[]),
+        "tce" => (|func, _| Ok(tail_call_elim(func)), &[]),
+        "sr" => (strength_reduction, &[]),
+        // not in `PASSES`, they only exist to break programs for the reducer's tests
+        "crashcalls" => (|func, _| crash_on_calls(func), CONTROL_FLOW_ANALYSES),
+        "deletecalls" => (|func, _| delete_calls(func), &[]),
+        _ => return None,
+    };
+    Some(Box::new(FunctionPass::new(name, transform, preserved)))
+}
+
+enum Stage {
+    Pass(Box<dyn Pass>),
+    FixedPoint(Vec<Stage>), // repeated until the program stops changing
+}
+
+pub struct PassManager {
+    stages: Vec<Stage>,
+    verify: bool,            // check the program is well formed after every pass
+    dump_after: Vec<String>, // names of the passes to dump the program after, or "all"
+    dump: RefCell<Option<Box<dyn Write>>>,
+}
+
+impl PassManager {
+    // Pipelines are comma separated pass names, e.g. "lvn,dce,ssa". Passes in brackets are
+    // repeated as a group until they reach a fixed point, e.g. "ssa,[fold,dce]". Passes with an
+    // option take it after `=`, e.g. "inline=50".
+    pub fn parse(pipeline: &str, verify: bool) -> Result<PassManager> {
+        Ok(PassManager {
+            stages: Self::parse_stages(&mut pipeline.chars().peekable(), false)?,
+            verify,
+            dump_after: vec![],
+            dump: RefCell::new(None),
+        })
+    }
+
+    // writes the program to `out` after every run of the named passes
+    pub fn dump_after(&mut self, passes: Vec<String>, out: Box<dyn Write>) -> Result<()> {
+        if let Some(name) = passes
+            .iter()
+            .find(|&name| name != "all" && !PASSES.contains(&name.as_str()))
+        {
+            return Err(Error::InvalidPipeline(format!(
+                "unknown pass '{}' to dump after, expected all or one of {}",
+                name,
+                PASSES.join(", ")
+            )));
+        }
+        self.dump_after = passes;
+        self.dump = RefCell::new(Some(out));
+        Ok(())
+    }
+
+    fn parse_stages(chars: &mut Peekable<Chars>, nested: bool) -> Result<Vec<Stage>> {
+        let mut stages = vec![];
+        let mut name = String::new();
+        loop {
+            let c = chars.next();
+            if let None | Some(',' | '[' | ']') = c {
+                if !name.is_empty() {
+                    let pass = create_pass(&name).ok_or_else(|| {
+                        Error::InvalidPipeline(format!(
+                            "unknown pass '{}', expected one of {}",
+                            name,
+                            PASSES.join(", ")
+                        ))
+                    })?;
+                    stages.push(Stage::Pass(pass));
+                    name.clear();
+                }
+            }
+            match c {
+                Some('[') => stages.push(Stage::FixedPoint(Self::parse_stages(chars, true)?)),
+                Some(']') if nested => return Ok(stages),
+                Some(']') => {
+                    return Err(Error::InvalidPipeline(String::from(
+                        "unmatched ']' in pipeline",
+                    )))
+                }
+                None if nested => {
+                    return Err(Error::InvalidPipeline(String::from(
+                        "unclosed '[' in pipeline",
+                    )))
+                }
+                None => return Ok(stages),
+                Some(',') => {}
+                Some(c) if c.is_whitespace() => {}
+                Some(c) => name.push(c),
+            }
+        }
+    }
+
+    pub fn run(&self, prog: &Program) -> Result<Program> {
+        self.run_stages(&self.stages, prog.clone(), &mut AnalysisManager::new())
+    }
+
+    fn run_stages(
+        &self,
+        stages: &[Stage],
+        mut prog: Program,
+        analyses: &mut AnalysisManager,
+    ) -> Result<Program> {
+        for stage in stages {
+            prog = match stage {
+                Stage::Pass(pass) => {
+                    analyses.set_program(&prog);
+                    let new_prog = pass.run_on_program(&prog, analyses)?;
+                    Self::invalidate_changed(pass.as_ref(), &prog, &new_prog, analyses);
+                    self.dump_program(pass.name(), &new_prog)?;
+                    if self.verify {
+                        verify_program(&new_prog).map_err(|err| {
+                            Error::AfterPass(String::from(pass.name()), Box::new(err))
+                        })?;
+                    }
+                    new_prog
+                }
+                Stage::FixedPoint(group) => {
+                    for _ in 0..MAX_ITERATIONS {
+                        let new_prog = self.run_stages(group, prog.clone(), analyses)?;
+                        if new_prog.functions == prog.functions {
+                            break;
+                        }
+                        prog = new_prog;
+                    }
+                    prog
+                }
+            }
+        }
+        Ok(prog)
+    }
+
+    fn dump_program(&self, pass_name: &str, prog: &Program) -> Result<()> {
+        if !self
+            .dump_after
+            .iter()
+            .any(|name| name == "all" || name == pass_name)
+        {
+            return Ok(());
+        }
+        if let Some(out) = self.dump.borrow_mut().as_mut() {
+            write!(out, "# after {}\n{}\n", pass_name, prog).map_err(|err| {
+                Error::Io(format!("cannot dump after pass '{}': {}", pass_name, err))
+            })?;
+        }
+        Ok(())
+    }
+
+    // drops the cached results the pass did not preserve for the functions it changed, and
+    // everything for functions it added or removed
+    fn invalidate_changed(
+        pass: &dyn Pass,
+        prog: &Program,
+        new_prog: &Program,
+        analyses: &mut AnalysisManager,
+    ) {
+        let old_funcs: HashMap<&String, &Function> = prog
+            .functions
+            .iter()
+            .map(|func| (&func.name, func))
+            .collect();
+        let new_funcs: HashMap<&String, &Function> = new_prog
+            .functions
+            .iter()
+            .map(|func| (&func.name, func))
+            .collect();
+        for (name, func) in old_funcs.iter() {
+            match new_funcs.get(name) {
+                Some(new_func) if new_func == func => {}
+                Some(_) => analyses.invalidate(name, pass.preserved_analyses()),
+                None => analyses.invalidate(name, &[]),
+            }
+        }
+        for name in new_funcs.keys() {
+            if !old_funcs.contains_key(name) {
+                analyses.invalidate(name, &[]);
+            }
+        }
+    }
+}
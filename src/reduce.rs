@@ -0,0 +1,191 @@
+use bril_rs::{Code, EffectOps, Function, Instruction, Program};
+
+use crate::{
+    callgraph::callees,
+    check::compare_limited,
+    error::Result,
+    fuzz::{catch_panic, quietly, MAX_INSTRUCTIONS},
+    interp::interpret_limited,
+    pass::PassManager,
+    verify::verify_program,
+};
+
+// what makes a program interesting to the reducer
+#[derive(Clone, Copy, PartialEq)]
+pub enum Predicate {
+    // some pass of the pipeline panics
+    Panic,
+    // the program prints or returns something else after the pipeline
+    Mismatch,
+}
+
+impl Predicate {
+    // Whether the problem shows on `prog`. Only well-formed programs count, so that deleting code
+    // cannot turn the problem into a different one, e.g. a use of an undefined variable.
+    pub fn holds(&self, prog: &Program, pipeline: &PassManager, args: &[String]) -> bool {
+        if verify_program(prog).is_err() {
+            return false;
+        }
+        match self {
+            Predicate::Panic => catch_panic(|| pipeline.run(prog)).is_err(),
+            Predicate::Mismatch => {
+                if !matches!(
+                    catch_panic(|| interpret_limited(prog, args, MAX_INSTRUCTIONS)),
+                    Ok(Ok(execution)) if execution.failure.is_none()
+                ) {
+                    return false;
+                }
+                match catch_panic(|| pipeline.run(prog)) {
+                    Ok(Ok(new_prog)) => catch_panic(|| {
+                        compare_limited(prog, &new_prog, args, MAX_INSTRUCTIONS).agrees()
+                    })
+                    .is_ok_and(|agrees| !agrees),
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+// The `crashcalls` pass, which breaks programs on purpose to test the reducer with, like LLVM's
+// bugpoint does: it panics on any function that calls another.
+pub fn crash_on_calls(func: &Function) -> Result<Function> {
+    if func.instrs.iter().any(|code| !callees(code).is_empty()) {
+        panic!("@{} makes a call", func.name);
+    }
+    Ok(func.clone())
+}
+
+// The `deletecalls` pass, which like `crashcalls` is for testing the reducer: it deletes every
+// call that does not produce a value, whatever the callee does.
+pub fn delete_calls(func: &Function) -> Result<Function> {
+    let mut new_func = func.clone();
+    new_func.instrs.retain(|code| {
+        !matches!(
+            code,
+            Code::Instruction(Instruction::Effect {
+                op: EffectOps::Call,
+                ..
+            })
+        )
+    });
+    Ok(new_func)
+}
+
+// Deletes chunks of `items` for as long as `holds` stays true on what is left, halving the chunk
+// size down to single items.
+fn reduce_list<T: Clone>(mut items: Vec<T>, holds: impl Fn(&[T]) -> bool) -> Vec<T> {
+    let mut chunk = (items.len() / 2).max(1);
+    loop {
+        let mut start = 0;
+        while start < items.len() {
+            let end = (start + chunk).min(items.len());
+            let candidate: Vec<T> = items[..start]
+                .iter()
+                .chain(items[end..].iter())
+                .cloned()
+                .collect();
+            if holds(&candidate) {
+                items = candidate;
+            } else {
+                start = end;
+            }
+        }
+        if chunk == 1 {
+            return items;
+        }
+        chunk /= 2;
+    }
+}
+
+// splits the code of a function at its labels
+fn blocks(instrs: &[Code]) -> Vec<Vec<Code>> {
+    let mut blocks: Vec<Vec<Code>> = vec![];
+    for code in instrs {
+        match (code, blocks.last_mut()) {
+            (Code::Instruction(_), Some(block)) => block.push(code.clone()),
+            _ => blocks.push(vec![code.clone()]),
+        }
+    }
+    blocks
+}
+
+// Shrinks `prog` while `holds` stays true of it by deleting functions, blocks and instructions
+// and by turning branches into jumps, until none of these deletions keeps it true.
+pub fn reduce(prog: &Program, holds: impl Fn(&Program) -> bool) -> Program {
+    let mut prog = prog.clone();
+    loop {
+        let before = prog.clone();
+
+        prog.functions = reduce_list(prog.functions.clone(), |functions| {
+            let mut candidate = prog.clone();
+            candidate.functions = functions.to_vec();
+            holds(&candidate)
+        });
+
+        for idx in 0..prog.functions.len() {
+            let with_instrs = |prog: &Program, instrs: Vec<Code>| {
+                let mut candidate = prog.clone();
+                candidate.functions[idx].instrs = instrs;
+                candidate
+            };
+
+            let kept = reduce_list(blocks(&prog.functions[idx].instrs), |blocks| {
+                holds(&with_instrs(&prog, blocks.concat()))
+            });
+            prog = with_instrs(&prog, kept.concat());
+
+            // a branch that always goes the same way is one less edge to look at
+            for i in 0..prog.functions[idx].instrs.len() {
+                let Code::Instruction(Instruction::Effect {
+                    op: EffectOps::Branch,
+                    labels,
+                    pos,
+                    ..
+                }) = &prog.functions[idx].instrs[i]
+                else {
+                    continue;
+                };
+                for label in labels.clone() {
+                    let mut instrs = prog.functions[idx].instrs.clone();
+                    instrs[i] = Code::Instruction(Instruction::Effect {
+                        args: vec![],
+                        funcs: vec![],
+                        labels: vec![label],
+                        op: EffectOps::Jump,
+                        pos: pos.clone(),
+                    });
+                    let candidate = with_instrs(&prog, instrs);
+                    if holds(&candidate) {
+                        prog = candidate;
+                        break;
+                    }
+                }
+            }
+
+            let kept = reduce_list(prog.functions[idx].instrs.clone(), |instrs| {
+                holds(&with_instrs(&prog, instrs.to_vec()))
+            });
+            prog = with_instrs(&prog, kept);
+        }
+
+        if prog.functions == before.functions {
+            return prog;
+        }
+    }
+}
+
+// reduces `prog` to a smaller program on which `predicate` still holds for `pipeline`, or None if
+// it does not hold to begin with
+pub fn reduce_for_pipeline(
+    prog: &Program,
+    pipeline: &PassManager,
+    predicate: Predicate,
+    args: &[String],
+) -> Option<Program> {
+    // every candidate that still panics would print its panic otherwise
+    quietly(|| {
+        let holds = |prog: &Program| predicate.holds(prog, pipeline, args);
+        holds(prog).then(|| reduce(prog, holds))
+    })
+}
@@ -0,0 +1,220 @@
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{Code, ConstOps, EffectOps, Function, Instruction, Literal, Program, ValueOps};
+
+use crate::{
+    analysis_manager::AnalysisManager,
+    error::{Error, Result},
+    inline::size,
+    optimize::{dead_variable_elim, lvn_block},
+    pass::{map_blocks, Pass},
+    summary::Summaries,
+};
+
+// instructions the `specialize` pass may add to the program in total
+pub const DEFAULT_SPECIALIZE_BUDGET: usize = 50;
+
+pub struct Specializer {
+    budget: usize,
+}
+
+impl Specializer {
+    pub fn new(budget: usize) -> Specializer {
+        Specializer { budget }
+    }
+}
+
+impl Pass for Specializer {
+    fn name(&self) -> &str {
+        "specialize"
+    }
+
+    fn run_on_program(&self, prog: &Program, analyses: &mut AnalysisManager) -> Result<Program> {
+        specialize(prog, self.budget, &analyses.function_summaries()?)
+    }
+}
+
+// the specialized clones made so far
+struct Clones<'a> {
+    funcs: HashMap<&'a String, &'a Function>,
+    summaries: &'a Summaries,
+    budget: usize,
+    taken: HashSet<String>,
+    // callee and the constant value of each parameter, or None if it is not constant
+    names: HashMap<(String, Vec<Option<Literal>>), String>,
+    functions: Vec<Function>,
+}
+
+// e.g. `fib.10` for @fib specialized to n = 10, `f._.true` for the second parameter of @f
+fn clone_name(callee: &String, values: &[Option<Literal>]) -> String {
+    let mut name = callee.clone();
+    for value in values {
+        name += &match value {
+            Some(Literal::Int(n)) if *n < 0 => format!(".neg{}", n.unsigned_abs()),
+            Some(value) => format!(".{}", value),
+            None => String::from("._"),
+        };
+    }
+    name
+}
+
+// The callee with the constant parameters turned into constants at its start, folded and with
+// what became dead removed.
+fn specialized_clone(
+    func: &Function,
+    values: &[Option<Literal>],
+    name: String,
+    summaries: &Summaries,
+) -> Result<Function> {
+    let mut instrs: Vec<Code> = func
+        .args
+        .iter()
+        .zip(values)
+        .filter_map(|(param, value)| {
+            Some(Code::Instruction(Instruction::Constant {
+                dest: param.name.clone(),
+                op: ConstOps::Const,
+                pos: func.pos.clone(),
+                const_type: param.arg_type.clone(),
+                value: value.clone()?,
+            }))
+        })
+        .collect();
+    instrs.extend(func.instrs.iter().cloned());
+    let clone = Function {
+        args: func
+            .args
+            .iter()
+            .zip(values)
+            .filter(|(_, value)| value.is_none())
+            .map(|(param, _)| param.clone())
+            .collect(),
+        instrs,
+        name,
+        pos: func.pos.clone(),
+        return_type: func.return_type.clone(),
+    };
+    dead_variable_elim(
+        &map_blocks(&clone, |block| {
+            lvn_block(&clone.name, block, true, summaries)
+        })?,
+        summaries,
+    )
+}
+
+impl<'a> Clones<'a> {
+    // The clone to call instead of `callee`, and the arguments that are left, if some arguments
+    // are constant and the clone fits in the budget.
+    fn clone_for(
+        &mut self,
+        callee: &String,
+        args: &[String],
+        consts: &HashMap<String, Literal>,
+    ) -> Result<Option<(String, Vec<String>)>> {
+        let Some(func) = self.funcs.get(callee).copied() else {
+            return Ok(None);
+        };
+        if func.args.len() != args.len() {
+            return Ok(None);
+        }
+        let values: Vec<Option<Literal>> =
+            args.iter().map(|arg| consts.get(arg).cloned()).collect();
+        if values.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+        let remaining = args
+            .iter()
+            .zip(values.iter())
+            .filter(|(_, value)| value.is_none())
+            .map(|(arg, _)| arg.clone())
+            .collect();
+        let key = (callee.clone(), values);
+        if let Some(name) = self.names.get(&key) {
+            return Ok(Some((name.clone(), remaining)));
+        }
+
+        let mut name = clone_name(callee, &key.1);
+        while self.taken.contains(&name) {
+            name = name + "_";
+        }
+        let clone = specialized_clone(func, &key.1, name.clone(), self.summaries)?;
+        if size(&clone) > self.budget {
+            return Ok(None);
+        }
+        self.budget -= size(&clone);
+        self.taken.insert(name.clone());
+        self.names.insert(key, name.clone());
+        self.functions.push(clone);
+        Ok(Some((name, remaining)))
+    }
+}
+
+// Redirects calls with constant arguments to clones of the callee specialized to those
+// constants, until the clones would add more than `budget` instructions. Arguments count as
+// constant if they are defined by a `const` earlier in the same block. Calls in the clones are
+// left for another run of the pass.
+pub fn specialize(prog: &Program, budget: usize, summaries: &Summaries) -> Result<Program> {
+    let mut clones = Clones {
+        funcs: prog
+            .functions
+            .iter()
+            .map(|func| (&func.name, func))
+            .collect(),
+        summaries,
+        budget,
+        taken: prog
+            .functions
+            .iter()
+            .map(|func| func.name.clone())
+            .collect(),
+        names: HashMap::new(),
+        functions: vec![],
+    };
+    let mut new_prog = prog.clone();
+    for func in new_prog.functions.iter_mut() {
+        let mut consts: HashMap<String, Literal> = HashMap::new();
+        for code in func.instrs.iter_mut() {
+            if let Code::Instruction(
+                Instruction::Value {
+                    args,
+                    funcs,
+                    op: ValueOps::Call,
+                    pos,
+                    ..
+                }
+                | Instruction::Effect {
+                    args,
+                    funcs,
+                    op: EffectOps::Call,
+                    pos,
+                    ..
+                },
+            ) = code
+            {
+                let Some(callee) = funcs.first() else {
+                    return Err(Error::Malformed {
+                        func: func.name.clone(),
+                        msg: String::from("call without a function"),
+                        pos: pos.clone(),
+                    });
+                };
+                if let Some((name, remaining)) = clones.clone_for(callee, args, &consts)? {
+                    *funcs = vec![name];
+                    *args = remaining;
+                }
+            }
+            match code {
+                Code::Label { .. } => consts.clear(),
+                Code::Instruction(Instruction::Constant { dest, value, .. }) => {
+                    consts.insert(dest.clone(), value.clone());
+                }
+                Code::Instruction(Instruction::Value { dest, .. }) => {
+                    consts.remove(dest);
+                }
+                Code::Instruction(Instruction::Effect { .. }) => {}
+            }
+        }
+    }
+    new_prog.functions.extend(clones.functions);
+    Ok(new_prog)
+}
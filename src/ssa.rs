@@ -3,19 +3,46 @@ use std::collections::{HashMap, HashSet};
 use bril_rs::{Code, Function, Instruction, Type, ValueOps};
 
 use crate::{
-    analyze::{dominance_frontier, dominator_tree},
-    parse::{block_name_to_idx, control_flow_graph, expanded_basic_blocks, get_block_name},
+    analysis_manager::AnalysisManager,
+    error::{Error, Result},
+    parse::{block_name_to_idx, expanded_basic_blocks, get_block_name},
     util::{invert_digraph, invert_hashset},
 };
 
-pub fn convert_to_ssa(func: &Function) -> Function {
+pub fn convert_to_ssa(func: &Function, analyses: &mut AnalysisManager) -> Result<Function> {
+    convert_vars_to_ssa(func, &defined_vars(func), analyses)
+}
+
+// names of the function's arguments and of every variable it assigns
+pub fn defined_vars(func: &Function) -> HashSet<String> {
+    func.instrs
+        .iter()
+        .filter_map(|code| {
+            if let Code::Instruction(instr) = code {
+                if let Instruction::Constant { dest, .. } | Instruction::Value { dest, .. } = instr
+                {
+                    return Some(dest.clone());
+                }
+            }
+            return None;
+        })
+        .chain(func.args.iter().map(|arg| arg.name.clone()))
+        .collect::<HashSet<String>>()
+}
+
+// Inserts phi nodes for and renames only the variables in `vars`, other variables are untouched
+pub fn convert_vars_to_ssa(
+    func: &Function,
+    vars: &HashSet<String>,
+    analyses: &mut AnalysisManager,
+) -> Result<Function> {
     // Insert phi nodes
     let mut blocks = expanded_basic_blocks(func);
-    let successors = control_flow_graph(func);
+    let successors = analyses.control_flow_graph(func)?;
     let predecessors = invert_digraph(&successors);
-    let dom_tree = dominator_tree(func);
+    let dom_tree = analyses.dominator_tree(func)?;
     let inv_dom_tree = invert_digraph(&dom_tree);
-    let frontier = dominance_frontier(func);
+    let frontier = analyses.dominance_frontier(func)?;
     let inv_frontier = invert_hashset(&frontier);
     let block_map = block_name_to_idx(func);
 
@@ -29,7 +56,12 @@ pub fn convert_to_ssa(func: &Function) -> Function {
                     if let Instruction::Constant { dest, .. } | Instruction::Value { dest, .. } =
                         instr
                     {
-                        return Some((get_block_name(block, block_idx, &func.name), dest.clone()));
+                        if vars.contains(dest) {
+                            return Some((
+                                get_block_name(block, block_idx, &func.name),
+                                dest.clone(),
+                            ));
+                        }
                     }
                 }
                 return None;
@@ -38,24 +70,18 @@ pub fn convert_to_ssa(func: &Function) -> Function {
         .chain(
             func.args
                 .iter()
+                .filter(|arg| vars.contains(&arg.name))
                 .map(|arg| (String::from("<func_arg>"), arg.name.clone())),
         )
         .collect();
 
-    let orig_var_names: HashSet<String> = func
-        .instrs
+    // new names must not collide with any variable, renamed or not
+    let taken_var_names = defined_vars(func);
+    let orig_var_names: HashSet<String> = taken_var_names
         .iter()
-        .filter_map(|code| {
-            if let Code::Instruction(instr) = code {
-                if let Instruction::Constant { dest, .. } | Instruction::Value { dest, .. } = instr
-                {
-                    return Some(dest.clone());
-                }
-            }
-            return None;
-        })
-        .chain(func.args.iter().map(|arg| arg.name.clone()))
-        .collect::<HashSet<String>>();
+        .filter(|&var| vars.contains(var))
+        .cloned()
+        .collect();
 
     // map variable names to definitions (block name, block idx, line no.)
     let mut var_defs: HashMap<String, Vec<(String, Type)>> = orig_var_names
@@ -99,23 +125,18 @@ pub fn convert_to_ssa(func: &Function) -> Function {
 
     // add phi blocks
     for var in &orig_var_names {
-        for (def_block_name, op_type) in &var_defs[var].clone() {
-            for sub_block_name in &frontier[def_block_name] {
+        // phis are definitions too, so their frontiers need phis as well
+        let mut worklist = var_defs[var].clone();
+        while let Some((def_block_name, op_type)) = worklist.pop() {
+            for sub_block_name in &frontier[&def_block_name] {
                 let sub_block_idx = block_map[sub_block_name];
 
-                // label must always be first instruction in block
+                // label must always be first instruction in block, phis take its position
                 let mut phi_idx = 0;
-                if let Code::Label { .. } = blocks[sub_block_idx][0] {
-                    if blocks[sub_block_idx].len() < 2 {
-                        continue;
-                    }
-                    if let Code::Instruction(Instruction::Value {
-                        op: ValueOps::Phi, ..
-                    }) = blocks[sub_block_idx][1]
-                    {
-                        continue;
-                    }
+                let mut phi_pos = None;
+                if let Code::Label { pos, .. } = &blocks[sub_block_idx][0] {
                     phi_idx = 1;
+                    phi_pos = pos.clone();
                 }
 
                 // check for phi block of same var
@@ -144,7 +165,7 @@ pub fn convert_to_ssa(func: &Function) -> Function {
                         funcs: vec![],
                         labels: vec![],
                         op: ValueOps::Phi,
-                        pos: None,
+                        pos: phi_pos,
                         op_type: op_type.clone(),
                     }),
                 );
@@ -154,6 +175,7 @@ pub fn convert_to_ssa(func: &Function) -> Function {
                     .get_mut(var)
                     .expect(&format!("Variable definition vec not found for {}", var))
                     .push((sub_block_name.clone(), op_type.clone()));
+                worklist.push((sub_block_name.clone(), op_type.clone()));
             }
         }
     }
@@ -169,18 +191,20 @@ pub fn convert_to_ssa(func: &Function) -> Function {
 
     // Rename variables
     fn rename(
+        func_name: &String,
         block_name: &String,
         var_names: &mut HashMap<String, Vec<(String, String)>>,
         blocks: &mut Vec<Vec<Code>>,
         block_map: &HashMap<String, usize>,
         orig_var_names: &HashSet<String>,
+        taken_var_names: &HashSet<String>,
         successors: &HashMap<String, Vec<String>>,
         predecessors: &HashMap<String, Vec<String>>,
         inv_frontier: &HashMap<String, HashSet<String>>,
         dom_tree: &HashMap<String, Vec<String>>,
         inv_dom_tree: &HashMap<String, Vec<String>>,
         name_counter: &mut HashMap<String, usize>,
-    ) {
+    ) -> Result<()> {
         let init_var_stacks = var_names.clone();
 
         let block = &mut blocks[block_map[block_name]];
@@ -191,15 +215,22 @@ pub fn convert_to_ssa(func: &Function) -> Function {
             }) = &instr
             {
                 // do nothing
-            } else if let Code::Instruction(Instruction::Effect { args, .. })
-            | Code::Instruction(Instruction::Value { args, .. }) = instr
+            } else if let Code::Instruction(Instruction::Effect { args, pos, .. })
+            | Code::Instruction(Instruction::Value { args, pos, .. }) = instr
             {
                 for i in 0..args.len() {
-                    args[i] = var_names[&args[i]]
-                        .last()
-                        .expect(&format!("Stack for variable '{}' is empty", &args[i]))
-                        .1
-                        .clone();
+                    if !orig_var_names.contains(&args[i]) {
+                        continue;
+                    }
+                    let Some((_, name)) = var_names.get(&args[i]).and_then(|stack| stack.last())
+                    else {
+                        return Err(Error::UndefinedVariable {
+                            func: func_name.clone(),
+                            var: args[i].clone(),
+                            pos: pos.clone(),
+                        });
+                    };
+                    args[i] = name.clone();
                 }
             }
 
@@ -207,8 +238,11 @@ pub fn convert_to_ssa(func: &Function) -> Function {
             if let Code::Instruction(Instruction::Constant { dest, .. })
             | Code::Instruction(Instruction::Value { dest, .. }) = instr
             {
+                if !orig_var_names.contains(dest) {
+                    continue;
+                }
                 let mut new_name = format!("{}.{}", dest, name_counter[dest]);
-                while orig_var_names.contains(&new_name) {
+                while taken_var_names.contains(&new_name) {
                     new_name = new_name + "_";
                 }
 
@@ -233,17 +267,18 @@ pub fn convert_to_ssa(func: &Function) -> Function {
                     dest,
                     args,
                     labels,
+                    pos,
                     ..
                 }) = code
                 {
-                    return Some((dest, args, labels));
+                    return Some((dest, args, labels, pos));
                 }
                 return None;
             });
 
-            // add info to phi nodes in successor block
-            for (phi_dest, args, labels) in phi_nodes {
-                let canonical_name = var_names
+            // add info to phi nodes in successor block, skipping phis of untouched variables
+            for (phi_dest, args, labels, pos) in phi_nodes {
+                let Some(canonical_name) = var_names
                     .iter()
                     .map(|(key, v)| {
                         (
@@ -257,52 +292,64 @@ pub fn convert_to_ssa(func: &Function) -> Function {
                         }
                         return None;
                     })
-                    .expect("Cannot find canonical name");
-                let (bname, vname) = var_names[canonical_name]
-                    .last()
-                    .expect(&format!("Stack for variable '{}' is empty", &phi_dest));
+                else {
+                    continue;
+                };
+                let Some((_, vname)) = var_names[canonical_name].last() else {
+                    return Err(Error::UndefinedVariable {
+                        func: func_name.clone(),
+                        var: canonical_name.clone(),
+                        pos: pos.clone(),
+                    });
+                };
+                // the value flows in along the edge from this block, not from its definition
                 args.push(vname.clone());
-                labels.push(bname.clone());
+                labels.push(block_name.clone());
             }
         }
 
         for sub_block in &dom_tree[block_name] {
             rename(
+                func_name,
                 sub_block,
                 var_names,
                 blocks,
                 block_map,
                 orig_var_names,
+                taken_var_names,
                 successors,
                 predecessors,
                 inv_frontier,
                 dom_tree,
                 inv_dom_tree,
                 name_counter,
-            );
+            )?;
         }
 
         var_names.clear();
         for (key, val) in init_var_stacks {
             var_names.insert(key, val);
         }
+        Ok(())
     }
 
     rename(
+        &func.name,
         &String::from("entry"),
         &mut var_names,
         &mut blocks,
         &block_map,
         &orig_var_names,
+        &taken_var_names,
         &successors,
         &predecessors,
         &inv_frontier,
         &dom_tree,
         &inv_dom_tree,
         &mut name_counter,
-    );
+    )?;
 
-    Function {
+    Ok(Function {
         args: func.args.clone(),
         instrs: blocks[1..blocks.len() - 1]
             .into_iter()
@@ -312,5 +359,5 @@ pub fn convert_to_ssa(func: &Function) -> Function {
         name: func.name.clone(),
         pos: func.pos.clone(),
         return_type: func.return_type.clone(),
-    }
+    })
 }
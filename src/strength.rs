@@ -0,0 +1,428 @@
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Position, Type, ValueOps};
+
+use crate::{
+    analysis_manager::AnalysisManager,
+    analyze::LiveVariables,
+    error::Result,
+    induction::{dest, flipped, induction_variables, LoopContext},
+    inline::{fresh_name, names},
+    parse::{expanded_basic_blocks, get_block_name, ControlFlowGraph},
+    util::{instruction_pos, int_constant_instr, retarget},
+};
+
+// Replaces multiplications of induction variables by constants with additions, in every loop
+// of the function. Loop by loop, since each one gets a preheader that changes the blocks.
+pub fn strength_reduction(func: &Function, analyses: &mut AnalysisManager) -> Result<Function> {
+    let headers: Vec<String> = analyses
+        .natural_loops(func)?
+        .iter()
+        .map(|natural_loop| natural_loop.header.clone())
+        .collect();
+    let mut func = func.clone();
+    for header in headers {
+        let loops = analyses.natural_loops(&func)?;
+        let Some(natural_loop) = loops
+            .iter()
+            .find(|natural_loop| natural_loop.header == header)
+        else {
+            continue;
+        };
+        let successors = analyses.control_flow_graph(&func)?;
+        let reaching = analyses.reaching_definitions(&func)?;
+        let live = analyses.live_variables(&func)?;
+        let ctx = LoopContext::new(&func, natural_loop, &reaching);
+        let new_func = reduce_loop(&ctx, &successors, &live);
+        // the cached analyses are of the function before this loop was reduced
+        if new_func != func {
+            analyses.invalidate(&func.name, &[]);
+            func = new_func;
+        }
+    }
+    Ok(func)
+}
+
+fn value(
+    op: ValueOps,
+    dest: &String,
+    args: [&String; 2],
+    op_type: Type,
+    pos: &Option<Position>,
+) -> Code {
+    Code::Instruction(Instruction::Value {
+        args: args.into_iter().cloned().collect(),
+        dest: dest.clone(),
+        funcs: vec![],
+        labels: vec![],
+        op,
+        pos: pos.clone(),
+        op_type,
+    })
+}
+
+fn code_pos(code: &Code) -> Option<Position> {
+    match code {
+        Code::Instruction(instr) => instruction_pos(instr),
+        Code::Label { pos, .. } => pos.clone(),
+    }
+}
+
+// a new variable that is kept at `scale * base + offset`, where `base` changes by `step`
+struct Reduced {
+    var: String,
+    scale: i64,
+    offset: i64,
+    step: i64,
+}
+
+// Lines of the loop's blocks that are replaced, by nothing if they are removed, and code that
+// is inserted after lines
+#[derive(Default)]
+struct Rewrites {
+    replaced: HashMap<(String, usize), Option<Code>>,
+    after: HashMap<(String, usize), Vec<Code>>,
+}
+
+// Every derived induction variable that is defined by a `mul` gets a new variable, which is
+// initialized in a preheader and incremented right after its basic induction variable. The
+// `mul` becomes a copy of the new variable.
+fn reduce_loop(ctx: &LoopContext, successors: &ControlFlowGraph, live: &LiveVariables) -> Function {
+    let func = ctx.func;
+    let natural_loop = ctx.natural_loop;
+    let ivs = induction_variables(ctx);
+    let mut muls: Vec<&String> = ivs
+        .iter()
+        .filter(|(var, iv)| {
+            let is_mul = matches!(
+                ctx.code(&ctx.definitions(var)[0]),
+                Code::Instruction(Instruction::Value {
+                    op: ValueOps::Mul,
+                    ..
+                })
+            );
+            &iv.base != *var && is_mul
+        })
+        .map(|(var, _)| var)
+        .collect();
+    muls.sort();
+    let blocks = expanded_basic_blocks(func);
+    let header_idx = blocks
+        .iter()
+        .enumerate()
+        .position(|(idx, block)| get_block_name(block, idx, &func.name) == natural_loop.header);
+    let Some(header_idx) = header_idx else {
+        return func.clone();
+    };
+    let Code::Label {
+        pos: header_pos, ..
+    } = &blocks[header_idx][0]
+    else {
+        return func.clone();
+    };
+    if muls.is_empty() {
+        return func.clone();
+    }
+
+    let mut taken = names(func);
+    let preheader = fresh_name(format!("{}.preheader", natural_loop.header), &mut taken);
+    let mut preheader_code: Vec<Code> = vec![];
+    let mut rewrites = Rewrites::default();
+    let mut reduced: HashMap<(&String, i64, i64), String> = HashMap::new();
+    let mut by_base: Vec<(&String, Reduced)> = vec![];
+    for var in muls {
+        let iv = &ivs[var];
+        let def = &ctx.definitions(var)[0];
+        let pos = code_pos(ctx.code(def));
+        let key = (&iv.base, iv.scale, iv.offset);
+        if !reduced.contains_key(&key) {
+            let new_var = fresh_name(format!("{}.sr", var), &mut taken);
+            let scale = fresh_name(format!("{}.scale", new_var), &mut taken);
+            let step = fresh_name(format!("{}.step", new_var), &mut taken);
+            preheader_code.push(int_constant_instr(&scale, iv.scale, &pos));
+            preheader_code.push(value(
+                ValueOps::Mul,
+                &new_var,
+                [&iv.base, &scale],
+                Type::Int,
+                &pos,
+            ));
+            if iv.offset != 0 {
+                let offset = fresh_name(format!("{}.offset", new_var), &mut taken);
+                preheader_code.push(int_constant_instr(&offset, iv.offset, &pos));
+                preheader_code.push(value(
+                    ValueOps::Add,
+                    &new_var,
+                    [&new_var, &offset],
+                    Type::Int,
+                    &pos,
+                ));
+            }
+            preheader_code.push(int_constant_instr(
+                &step,
+                iv.scale.wrapping_mul(iv.step),
+                &pos,
+            ));
+            let base_def = &ctx.definitions(&iv.base)[0];
+            rewrites
+                .after
+                .entry((base_def.block.clone(), base_def.line))
+                .or_default()
+                .push(value(
+                    ValueOps::Add,
+                    &new_var,
+                    [&new_var, &step],
+                    Type::Int,
+                    &code_pos(ctx.code(base_def)),
+                ));
+            if !by_base.iter().any(|(base, _)| *base == &iv.base) {
+                let new = Reduced {
+                    var: new_var.clone(),
+                    scale: iv.scale,
+                    offset: iv.offset,
+                    step: iv.step,
+                };
+                by_base.push((&iv.base, new));
+            }
+            reduced.insert(key, new_var);
+        }
+        let copy = Code::Instruction(Instruction::Value {
+            args: vec![reduced[&key].clone()],
+            dest: var.clone(),
+            funcs: vec![],
+            labels: vec![],
+            op: ValueOps::Id,
+            pos,
+            op_type: Type::Int,
+        });
+        rewrites
+            .replaced
+            .insert((def.block.clone(), def.line), Some(copy));
+    }
+
+    // basic induction variables that were only left to decide when to leave the loop
+    for (base, new) in by_base.iter() {
+        if let Some((line, compare, bound)) =
+            reduced_compare(ctx, successors, live, &rewrites, base, new, &mut taken)
+        {
+            let base_def = &ctx.definitions(base)[0];
+            rewrites
+                .replaced
+                .insert((base_def.block.clone(), base_def.line), None);
+            rewrites
+                .replaced
+                .insert((natural_loop.header.clone(), line), Some(compare));
+            preheader_code.push(bound);
+        }
+    }
+
+    let mut instrs = vec![];
+    for (idx, block) in blocks.iter().enumerate().take(blocks.len() - 1).skip(1) {
+        let name = get_block_name(block, idx, &func.name);
+        if idx == header_idx {
+            // a block of the loop that falls into the header has to jump over the preheader
+            let prev = &blocks[idx - 1];
+            let falls_through = !matches!(
+                prev.last(),
+                Some(Code::Instruction(Instruction::Effect {
+                    op: EffectOps::Jump | EffectOps::Branch | EffectOps::Return,
+                    ..
+                }))
+            );
+            let prev_in_loop =
+                natural_loop
+                    .blocks
+                    .contains(&get_block_name(prev, idx - 1, &func.name));
+            if idx > 1 && falls_through && prev_in_loop {
+                instrs.push(Code::Instruction(Instruction::Effect {
+                    args: vec![],
+                    funcs: vec![],
+                    labels: vec![natural_loop.header.clone()],
+                    op: EffectOps::Jump,
+                    pos: header_pos.clone(),
+                }));
+            }
+            instrs.push(Code::Label {
+                label: preheader.clone(),
+                pos: header_pos.clone(),
+            });
+            instrs.append(&mut preheader_code);
+        }
+        if !natural_loop.blocks.contains(&name) {
+            // everything from outside the loop enters it through the preheader
+            instrs.extend(
+                block
+                    .iter()
+                    .map(|code| retarget(code, &natural_loop.header, &preheader)),
+            );
+            continue;
+        }
+        for (line, code) in block.iter().enumerate() {
+            let key = (name.clone(), line);
+            match rewrites.replaced.get(&key) {
+                Some(replacement) => instrs.extend(replacement.clone()),
+                None => instrs.push(code.clone()),
+            }
+            if let Some(codes) = rewrites.after.get(&key) {
+                instrs.extend(codes.iter().cloned());
+            }
+        }
+    }
+
+    Function {
+        args: func.args.clone(),
+        instrs,
+        name: func.name.clone(),
+        pos: func.pos.clone(),
+        return_type: func.return_type.clone(),
+    }
+}
+
+// Whether `block` can run again without going through the header first, like in an inner loop
+fn repeats_within_iteration(
+    ctx: &LoopContext,
+    successors: &ControlFlowGraph,
+    block: &String,
+) -> bool {
+    let natural_loop = ctx.natural_loop;
+    if block == &natural_loop.header {
+        return false;
+    }
+    let mut seen: HashSet<&String> = HashSet::new();
+    let mut stack: Vec<&String> = successors[block].iter().collect();
+    while let Some(next) = stack.pop() {
+        if next == block {
+            return true;
+        }
+        if next == &natural_loop.header || !natural_loop.blocks.contains(next) || !seen.insert(next)
+        {
+            continue;
+        }
+        stack.extend(successors[next].iter());
+    }
+    false
+}
+
+// The basic induction variable can be removed if, besides its own definition, it is only used by
+// the comparison that decides whether the header leaves the loop, and is dead after the loop.
+// Returns the line of the comparison, the comparison of the new variable that replaces it, and
+// the definition of the new bound. Only done if neither variable can overflow before the loop
+// is left.
+fn reduced_compare(
+    ctx: &LoopContext,
+    successors: &ControlFlowGraph,
+    live: &LiveVariables,
+    rewrites: &Rewrites,
+    base: &String,
+    new: &Reduced,
+    taken: &mut HashSet<String>,
+) -> Option<(usize, Code, Code)> {
+    let natural_loop = ctx.natural_loop;
+    let base_def = &ctx.definitions(base)[0];
+    let mut uses = vec![];
+    for (name, block) in ctx.loop_blocks() {
+        // the variable has to be dead wherever the loop is left
+        let live_after = successors[&name]
+            .iter()
+            .any(|succ| !natural_loop.blocks.contains(succ) && live[succ].0.contains(base));
+        if live_after {
+            return None;
+        }
+        for (line, code) in block.iter().enumerate() {
+            let code = match rewrites.replaced.get(&(name.clone(), line)) {
+                Some(Some(replacement)) => replacement,
+                Some(None) => continue,
+                None => code,
+            };
+            if let Code::Instruction(
+                Instruction::Value { args, .. } | Instruction::Effect { args, .. },
+            ) = code
+            {
+                if args.contains(base) && (&name, line) != (&base_def.block, base_def.line) {
+                    uses.push((name.clone(), line, code));
+                }
+            }
+        }
+    }
+    let [(block, line, compare)] = uses.as_slice() else {
+        return None;
+    };
+    if block != &natural_loop.header || repeats_within_iteration(ctx, successors, &base_def.block) {
+        return None;
+    }
+    let header = ctx.block(block);
+    let Code::Instruction(Instruction::Value {
+        args,
+        dest: cond,
+        op,
+        pos,
+        ..
+    }) = compare
+    else {
+        return None;
+    };
+    let (bound, base_first) = match args.as_slice() {
+        [x, bound] if x == base => (bound, true),
+        [bound, x] if x == base => (bound, false),
+        _ => return None,
+    };
+    // an `eq` could be stepped over, and then nothing keeps the variables from overflowing
+    let flipped_op = flipped(op)?;
+
+    // the comparison decides the branch at the end of the header
+    let Some(Code::Instruction(Instruction::Effect {
+        op: EffectOps::Branch,
+        args: br_args,
+        labels,
+        ..
+    })) = header.last()
+    else {
+        return None;
+    };
+    let exits = labels
+        .iter()
+        .filter(|label| !natural_loop.blocks.contains(*label))
+        .count();
+    let redefined = header[line + 1..]
+        .iter()
+        .any(|code| dest(code) == Some(cond));
+    if br_args.first() != Some(cond) || exits != 1 || redefined {
+        return None;
+    }
+
+    // the variable approaches the bound and is compared after every step, so it never gets more
+    // than a step past it
+    let start = ctx.entry_constant(base)? as i128;
+    let bound_value = ctx.constant(bound)? as i128;
+    let step = new.step as i128;
+    if new.scale == 0 || (bound_value - start).signum() != step.signum() {
+        return None;
+    }
+    let reduced_value = |x: i128| -> Option<i64> {
+        let value = (new.scale as i128).checked_mul(x)? + new.offset as i128;
+        i64::try_from(value).ok()
+    };
+    let low = start.min(bound_value) - step.abs();
+    let high = start.max(bound_value) + step.abs();
+    i64::try_from(low).ok()?;
+    i64::try_from(high).ok()?;
+    reduced_value(low)?;
+    reduced_value(high)?;
+
+    let new_bound = fresh_name(format!("{}.bound", new.var), taken);
+    let new_op = if new.scale > 0 {
+        op.clone()
+    } else {
+        flipped_op
+    };
+    let new_args = if base_first {
+        [&new.var, &new_bound]
+    } else {
+        [&new_bound, &new.var]
+    };
+    Some((
+        *line,
+        value(new_op, cond, new_args, Type::Bool, pos),
+        int_constant_instr(&new_bound, reduced_value(bound_value)?, pos),
+    ))
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Program, ValueOps};
+
+use crate::{
+    callgraph::{call_graph, callees, recursive_functions, strongly_connected_components},
+    error::Result,
+    parse::control_flow_graph,
+};
+
+// what calling a function may do besides computing its return value
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub reads_memory: bool,
+    pub writes_memory: bool, // stores, and allocations and frees, which change the heap too
+    pub prints: bool,
+    pub may_not_terminate: bool, // loops or recursion, which are not looked into any further
+}
+
+pub type Summaries = HashMap<String, Summary>;
+
+impl Summary {
+    // for functions outside the program, whose code is not known
+    pub fn unknown() -> Summary {
+        Summary {
+            reads_memory: true,
+            writes_memory: true,
+            prints: true,
+            may_not_terminate: true,
+        }
+    }
+
+    // Calls to pure functions are plain values: the same arguments always give the same
+    // result, and an unused result can be dropped along with the call.
+    pub fn is_pure(&self) -> bool {
+        *self == Summary::default()
+    }
+
+    fn join(&mut self, other: &Summary) {
+        self.reads_memory |= other.reads_memory;
+        self.writes_memory |= other.writes_memory;
+        self.prints |= other.prints;
+        self.may_not_terminate |= other.may_not_terminate;
+    }
+}
+
+// what the function's own instructions do, leaving out its calls
+fn local_summary(func: &Function) -> Result<Summary> {
+    let mut summary = Summary::default();
+    for code in func.instrs.iter() {
+        match code {
+            Code::Instruction(Instruction::Value {
+                op: ValueOps::Load, ..
+            }) => summary.reads_memory = true,
+            Code::Instruction(Instruction::Value {
+                op: ValueOps::Alloc,
+                ..
+            })
+            | Code::Instruction(Instruction::Effect {
+                op: EffectOps::Store | EffectOps::Free,
+                ..
+            }) => summary.writes_memory = true,
+            Code::Instruction(Instruction::Effect {
+                op: EffectOps::Print,
+                ..
+            }) => summary.prints = true,
+            _ => {}
+        }
+    }
+    // blocks in a cycle of the control flow graph are found just like recursive functions
+    summary.may_not_terminate = !recursive_functions(&control_flow_graph(func)?).is_empty();
+    Ok(summary)
+}
+
+// Summarizes the functions bottom-up over the call graph, so that a function's summary includes
+// everything its callees may do. Functions that call each other share one summary.
+pub fn function_summaries(prog: &Program) -> Result<Summaries> {
+    let graph = call_graph(prog);
+    let funcs: HashMap<&String, &Function> = prog
+        .functions
+        .iter()
+        .map(|func| (&func.name, func))
+        .collect();
+    let mut summaries = Summaries::new();
+    for component in strongly_connected_components(&graph) {
+        let mut summary = Summary {
+            may_not_terminate: component.len() > 1 || graph[&component[0]].contains(&component[0]),
+            ..Summary::default()
+        };
+        for name in component.iter() {
+            summary.join(&local_summary(funcs[name])?);
+            for callee in graph[name]
+                .iter()
+                .filter(|callee| !component.contains(callee))
+            {
+                summary.join(summaries.get(callee).unwrap_or(&Summary::unknown()));
+            }
+        }
+        for name in component {
+            summaries.insert(name, summary.clone());
+        }
+    }
+    Ok(summaries)
+}
+
+// whether the instruction is a call to a pure function
+pub fn is_pure_call(code: &Code, summaries: &Summaries) -> bool {
+    let funcs = callees(code);
+    !funcs.is_empty()
+        && funcs
+            .iter()
+            .all(|func| summaries.get(func).is_some_and(Summary::is_pure))
+}
@@ -0,0 +1,120 @@
+use bril_rs::{Code, EffectOps, Function, Instruction, ValueOps};
+
+use crate::{
+    inline::{fresh_name, names},
+    util::instruction_pos,
+};
+
+// the arguments of a call to `func` itself that is immediately returned
+fn self_tail_call<'a>(func: &Function, call: &'a Code, ret: &Code) -> Option<&'a Vec<String>> {
+    let Code::Instruction(Instruction::Effect {
+        op: EffectOps::Return,
+        args: ret_args,
+        ..
+    }) = ret
+    else {
+        return None;
+    };
+    match call {
+        Code::Instruction(Instruction::Value {
+            args,
+            dest,
+            funcs,
+            op: ValueOps::Call,
+            ..
+        }) if funcs == &[func.name.clone()] && ret_args == &[dest.clone()] => Some(args),
+        Code::Instruction(Instruction::Effect {
+            args,
+            funcs,
+            op: EffectOps::Call,
+            ..
+        }) if funcs == &[func.name.clone()] && ret_args.is_empty() => Some(args),
+        _ => None,
+    }
+}
+
+// Turns calls of the function to itself that are immediately returned into jumps to a new label
+// at the top of the function, after assigning the arguments to the parameters. The recursion
+// becomes a loop, which runs in constant stack space.
+pub fn tail_call_elim(func: &Function) -> Function {
+    let mut taken = names(func);
+    let header = fresh_name(format!("{}.tail", func.name), &mut taken);
+    let mut instrs = vec![];
+    let mut found = false;
+    let mut i = 0;
+    while i < func.instrs.len() {
+        let call = &func.instrs[i];
+        let tail_call = func
+            .instrs
+            .get(i + 1)
+            .and_then(|ret| self_tail_call(func, call, ret))
+            .filter(|args| args.len() == func.args.len());
+        let Some(args) = tail_call else {
+            instrs.push(call.clone());
+            i += 1;
+            continue;
+        };
+        let pos = match call {
+            Code::Instruction(instr) => instruction_pos(instr),
+            Code::Label { .. } => None,
+        };
+        let copy = |dest: String, arg: String, op_type| {
+            Code::Instruction(Instruction::Value {
+                args: vec![arg],
+                dest,
+                funcs: vec![],
+                labels: vec![],
+                op: ValueOps::Id,
+                pos: pos.clone(),
+                op_type,
+            })
+        };
+        // copy through temporaries, since an argument may be a parameter that is reassigned
+        let changed: Vec<_> = func
+            .args
+            .iter()
+            .zip(args)
+            .filter(|(param, arg)| &param.name != *arg)
+            .map(|(param, arg)| {
+                let temp = fresh_name(format!("{}.tail", param.name), &mut taken);
+                (param, arg, temp)
+            })
+            .collect();
+        for (param, arg, temp) in changed.iter() {
+            instrs.push(copy(temp.clone(), (*arg).clone(), param.arg_type.clone()));
+        }
+        for (param, _, temp) in changed.iter() {
+            instrs.push(copy(
+                param.name.clone(),
+                temp.clone(),
+                param.arg_type.clone(),
+            ));
+        }
+        instrs.push(Code::Instruction(Instruction::Effect {
+            args: vec![],
+            funcs: vec![],
+            labels: vec![header.clone()],
+            op: EffectOps::Jump,
+            pos: pos.clone(),
+        }));
+        found = true;
+        i += 2;
+    }
+    if !found {
+        return func.clone();
+    }
+    instrs.insert(
+        0,
+        Code::Label {
+            label: header,
+            pos: func.pos.clone(),
+        },
+    );
+    Function {
+        args: func.args.clone(),
+        instrs,
+        name: func.name.clone(),
+        pos: func.pos.clone(),
+        return_type: func.return_type.clone(),
+    }
+}
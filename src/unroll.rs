@@ -0,0 +1,364 @@
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Position, Type, ValueOps};
+
+use crate::{
+    analysis_manager::AnalysisManager,
+    analyze::{DataFlowAnalysis, NaturalLoop},
+    error::Result,
+    induction::{dest, flipped, induction_variables, LoopContext},
+    inline::{fresh_name, names},
+    optimize::lvn_block,
+    parse::{basic_blocks, BasicBlock},
+    pass::Pass,
+    summary::Summaries,
+    util::{int_constant_instr, retarget},
+};
+
+// loops that run at most this many times are unrolled completely...
+const FULL_UNROLL_TRIPS: i64 = 8;
+// ...as long as that takes at most this many instructions
+const FULL_UNROLL_SIZE: usize = 128;
+// how many iterations the partially unrolled loops do at once
+pub const DEFAULT_UNROLL_FACTOR: usize = 4;
+
+pub struct Unroller {
+    factor: usize,
+}
+
+impl Unroller {
+    pub fn new(factor: usize) -> Unroller {
+        Unroller { factor }
+    }
+}
+
+impl Pass for Unroller {
+    fn name(&self) -> &str {
+        "unroll"
+    }
+
+    fn run_on_function(&self, func: &Function, analyses: &mut AnalysisManager) -> Result<Function> {
+        let loops = analyses.natural_loops(func)?;
+        let reaching = analyses.reaching_definitions(func)?;
+        let summaries = analyses.function_summaries()?;
+        unroll_loops(func, &loops, &reaching, &summaries, self.factor)
+    }
+}
+
+// A loop of a header block ending in `br cond .body .exit`, where `cond` compares the induction
+// variable with a constant bound, and a body block ending in `jmp .header`, which steps the
+// induction variable by a constant once. The header runs `trips + 1` times.
+struct CountedLoop {
+    header: String,
+    body: String,
+    exit: String,
+    iv: String,
+    start: i64,
+    step: i64,
+    bound_var: String,
+    bound: i64,
+    trips: i64,
+}
+
+// the block without its label and terminator
+fn middle(block: &BasicBlock) -> &[Code] {
+    &block[1..block.len() - 1]
+}
+
+// Number of times `iv` passes the comparison `iv op bound` when it starts at `start` and moves
+// by `step`, or `None` if it never fails or `iv` would overflow.
+fn trip_count(op: ValueOps, start: i64, step: i64, bound: i64) -> Option<i64> {
+    let (start, step, bound) = (start as i128, step as i128, bound as i128);
+    // the inclusive last value of `iv` that passes, and whether `iv` must increase to get there
+    let (last, increasing) = match op {
+        ValueOps::Lt => (bound - 1, true),
+        ValueOps::Le => (bound, true),
+        ValueOps::Gt => (bound + 1, false),
+        ValueOps::Ge => (bound, false),
+        _ => return None,
+    };
+    if (increasing && start > last) || (!increasing && start < last) {
+        return Some(0);
+    }
+    if (increasing && step <= 0) || (!increasing && step >= 0) {
+        return None;
+    }
+    let trips = (last - start) / step + 1;
+    let end = start + trips * step;
+    if end < i64::MIN as i128 || end > i64::MAX as i128 {
+        return None;
+    }
+    i64::try_from(trips).ok()
+}
+
+fn negated(op: &ValueOps) -> Option<ValueOps> {
+    match op {
+        ValueOps::Lt => Some(ValueOps::Ge),
+        ValueOps::Le => Some(ValueOps::Gt),
+        ValueOps::Gt => Some(ValueOps::Le),
+        ValueOps::Ge => Some(ValueOps::Lt),
+        _ => None,
+    }
+}
+
+fn counted_loop(
+    func: &Function,
+    natural_loop: &NaturalLoop,
+    blocks: &HashMap<&String, &BasicBlock>,
+    reaching: &DataFlowAnalysis,
+) -> Option<CountedLoop> {
+    let [body] = natural_loop.latches.as_slice() else {
+        return None;
+    };
+    if natural_loop.blocks.len() != 2 || body == &natural_loop.header {
+        return None;
+    }
+    let header_block = blocks.get(&natural_loop.header)?;
+    let body_block = blocks.get(body)?;
+    let Some(Code::Instruction(Instruction::Effect {
+        op: EffectOps::Branch,
+        args: br_args,
+        labels,
+        ..
+    })) = header_block.last()
+    else {
+        return None;
+    };
+    let (exit, loops_if_true) = match labels.as_slice() {
+        [taken, exit] if taken == body => (exit, true),
+        [exit, taken] if taken == body => (exit, false),
+        _ => return None,
+    };
+    if natural_loop.blocks.contains(exit) {
+        return None;
+    }
+    let Some(Code::Instruction(Instruction::Effect {
+        op: EffectOps::Jump,
+        ..
+    })) = body_block.last()
+    else {
+        return None;
+    };
+
+    // the last definition of the branch condition before the branch
+    let Some(Code::Instruction(Instruction::Value {
+        op: compare_op,
+        args: compare_args,
+        ..
+    })) = middle(header_block)
+        .iter()
+        .rev()
+        .find(|code| dest(code) == br_args.first())
+    else {
+        return None;
+    };
+    let ctx = LoopContext::new(func, natural_loop, reaching);
+    let ivs = induction_variables(&ctx);
+
+    // the induction variable is the compared basic induction variable, which the body steps
+    let [a, b] = compare_args.as_slice() else {
+        return None;
+    };
+    let (iv, bound, op) = [(a, b, compare_op.clone()), (b, a, flipped(compare_op)?)]
+        .into_iter()
+        .find(|(iv, ..)| {
+            ivs.get(*iv).is_some_and(|induction| &induction.base == *iv)
+                && ctx.definitions(iv).iter().all(|def| &def.block == body)
+        })?;
+    let op = if loops_if_true { op } else { negated(&op)? };
+    let start = ctx.entry_constant(iv)?;
+    let step = ivs[iv].step;
+    let bound_value = ctx.constant(bound)?;
+    Some(CountedLoop {
+        header: natural_loop.header.clone(),
+        body: body.clone(),
+        exit: exit.clone(),
+        iv: iv.clone(),
+        start,
+        step,
+        bound_var: bound.clone(),
+        bound: bound_value,
+        trips: trip_count(op, start, step, bound_value)?,
+    })
+}
+
+fn label_of(block: &BasicBlock) -> Option<&String> {
+    match block.first() {
+        Some(Code::Label { label, .. }) => Some(label),
+        _ => None,
+    }
+}
+
+fn branch_pos(header: &BasicBlock) -> Option<Position> {
+    match header.last() {
+        Some(Code::Instruction(Instruction::Effect { pos, .. })) => pos.clone(),
+        _ => None,
+    }
+}
+
+// `copies` iterations of the loop, one after the other
+fn repeated(header: &BasicBlock, body: &BasicBlock, copies: i64) -> Vec<Code> {
+    (0..copies)
+        .flat_map(|_| middle(header).iter().chain(middle(body)).cloned())
+        .collect()
+}
+
+// Unrolls the counted loops of the function. Loops that run at most a few times become
+// straight-line code, other loops run `factor` iterations at a time until fewer than `factor`
+// are left, which the original loop then does. The unrolled code is simplified with `lvn_block`.
+pub fn unroll_loops(
+    func: &Function,
+    loops: &[NaturalLoop],
+    reaching: &DataFlowAnalysis,
+    summaries: &Summaries,
+    factor: usize,
+) -> Result<Function> {
+    let blocks = basic_blocks(func);
+    let by_label: HashMap<&String, &BasicBlock> = blocks
+        .iter()
+        .filter_map(|block| Some((label_of(block)?, block)))
+        .collect();
+    let counted: Vec<CountedLoop> = loops
+        .iter()
+        .filter_map(|natural_loop| counted_loop(func, natural_loop, &by_label, reaching))
+        .collect();
+    if counted.is_empty() {
+        return Ok(func.clone());
+    }
+
+    let mut taken = names(func);
+    let mut full: HashMap<&String, &CountedLoop> = HashMap::new();
+    let mut partial: HashMap<&String, (&CountedLoop, String)> = HashMap::new();
+    for candidate in counted.iter() {
+        let size =
+            middle(by_label[&candidate.header]).len() + middle(by_label[&candidate.body]).len();
+        if candidate.trips <= FULL_UNROLL_TRIPS
+            && (candidate.trips as usize + 1) * size <= FULL_UNROLL_SIZE
+        {
+            full.insert(&candidate.header, candidate);
+        } else if factor > 1 && candidate.trips >= factor as i64 {
+            let label = fresh_name(format!("{}.unroll", candidate.header), &mut taken);
+            partial.insert(&candidate.header, (candidate, label));
+        }
+    }
+    let full_bodies: HashSet<&String> = full.values().map(|counted| &counted.body).collect();
+
+    let mut instrs = vec![];
+    for block in blocks.iter() {
+        let label = label_of(block);
+        if label.is_some_and(|label| full_bodies.contains(label)) {
+            continue;
+        }
+        if let Some(counted) = label.and_then(|label| full.get(label)) {
+            let body = by_label[&counted.body];
+            let pos = branch_pos(block);
+            // the values on entry, so that value numbering can fold the conditions
+            let mut unrolled = vec![
+                block[0].clone(),
+                int_constant_instr(&counted.iv, counted.start, &pos),
+                int_constant_instr(&counted.bound_var, counted.bound, &pos),
+            ];
+            unrolled.extend(repeated(block, body, counted.trips));
+            unrolled.extend(middle(block).iter().cloned());
+            unrolled.push(Code::Instruction(Instruction::Effect {
+                args: vec![],
+                funcs: vec![],
+                labels: vec![counted.exit.clone()],
+                op: EffectOps::Jump,
+                pos,
+            }));
+            instrs.extend(lvn_block(&func.name, &unrolled, true, summaries)?);
+            continue;
+        }
+        if let Some((counted, unroll_label)) = label.and_then(|label| partial.get(label)) {
+            let (check, unrolled) = partially_unrolled(
+                counted,
+                unroll_label,
+                block,
+                by_label[&counted.body],
+                factor as i64,
+                &mut taken,
+            );
+            instrs.extend(check);
+            instrs.extend(lvn_block(&func.name, &unrolled, true, summaries)?);
+            instrs.extend(block.iter().cloned());
+            continue;
+        }
+        // code outside a partially unrolled loop enters it through the unrolled copy, the
+        // original loop only runs the remaining iterations
+        instrs.extend(block.iter().map(|code| {
+            partial
+                .values()
+                .filter(|(counted, _)| label != Some(&counted.body))
+                .fold(code.clone(), |code, (counted, unroll_label)| {
+                    retarget(&code, &counted.header, unroll_label)
+                })
+        }));
+    }
+
+    Ok(Function {
+        args: func.args.clone(),
+        instrs,
+        name: func.name.clone(),
+        pos: func.pos.clone(),
+        return_type: func.return_type.clone(),
+    })
+}
+
+// A loop doing `factor` iterations of `counted` at a time, placed before the original loop,
+// which does the remaining iterations: the block checking that `factor` iterations are left, and
+// the block doing them, which is yet to be simplified.
+fn partially_unrolled(
+    counted: &CountedLoop,
+    label: &String,
+    header: &BasicBlock,
+    body: &BasicBlock,
+    factor: i64,
+    taken: &mut HashSet<String>,
+) -> (Vec<Code>, BasicBlock) {
+    let pos = branch_pos(header);
+    let body_label = fresh_name(format!("{}.body", label), taken);
+    let limit = fresh_name(format!("{}.limit", counted.iv), taken);
+    let more = fresh_name(format!("{}.more", counted.iv), taken);
+    let unrolled_trips = counted.trips - counted.trips % factor;
+    let check = vec![
+        Code::Label {
+            label: label.clone(),
+            pos: pos.clone(),
+        },
+        int_constant_instr(&limit, counted.start + unrolled_trips * counted.step, &pos),
+        Code::Instruction(Instruction::Value {
+            args: vec![counted.iv.clone(), limit],
+            dest: more.clone(),
+            funcs: vec![],
+            labels: vec![],
+            op: if counted.step > 0 {
+                ValueOps::Lt
+            } else {
+                ValueOps::Gt
+            },
+            pos: pos.clone(),
+            op_type: Type::Bool,
+        }),
+        Code::Instruction(Instruction::Effect {
+            args: vec![more],
+            funcs: vec![],
+            labels: vec![body_label.clone(), counted.header.clone()],
+            op: EffectOps::Branch,
+            pos: pos.clone(),
+        }),
+    ];
+    let mut unrolled = vec![Code::Label {
+        label: body_label,
+        pos: pos.clone(),
+    }];
+    unrolled.extend(repeated(header, body, factor));
+    unrolled.push(Code::Instruction(Instruction::Effect {
+        args: vec![],
+        funcs: vec![],
+        labels: vec![label.clone()],
+        op: EffectOps::Jump,
+        pos,
+    }));
+    (check, unrolled)
+}
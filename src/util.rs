@@ -2,6 +2,8 @@ use std::collections::HashSet;
 use std::fmt::Write;
 use std::{collections::HashMap, error::Error};
 
+use bril_rs::{Code, ConstOps, Instruction, Literal, Position, Type};
+
 pub type DiGraph = HashMap<String, Vec<String>>;
 
 pub fn graphviz(digraph: &DiGraph, name: &String) -> Result<String, Box<dyn Error>> {
@@ -82,3 +84,40 @@ pub fn postorder_traversal(
     }
     return new_postorder;
 }
+
+// "row:col" of a source position, or "?" if the program was parsed without positions
+pub fn position_string(pos: &Option<Position>) -> String {
+    match pos {
+        Some(pos) => format!("{}:{}", pos.pos.row, pos.pos.col),
+        None => String::from("?"),
+    }
+}
+
+pub fn instruction_pos(instr: &Instruction) -> Option<Position> {
+    match instr {
+        Instruction::Constant { pos, .. }
+        | Instruction::Value { pos, .. }
+        | Instruction::Effect { pos, .. } => pos.clone(),
+    }
+}
+
+pub fn int_constant_instr(dest: &String, value: i64, pos: &Option<Position>) -> Code {
+    Code::Instruction(Instruction::Constant {
+        dest: dest.clone(),
+        op: ConstOps::Const,
+        pos: pos.clone(),
+        const_type: Type::Int,
+        value: Literal::Int(value),
+    })
+}
+
+// the jump or branch with its target `from` replaced by `to`
+pub fn retarget(code: &Code, from: &String, to: &String) -> Code {
+    let mut code = code.clone();
+    if let Code::Instruction(Instruction::Effect { labels, .. }) = &mut code {
+        for label in labels.iter_mut().filter(|label| *label == from) {
+            *label = to.clone();
+        }
+    }
+    code
+}
@@ -0,0 +1,154 @@
+use std::collections::{HashMap, HashSet};
+
+use bril_rs::{Code, EffectOps, Function, Instruction, Program, ValueOps};
+
+use crate::{
+    error::{Error, Result},
+    ssa::defined_vars,
+    util::instruction_pos,
+};
+
+// number of arguments an operation takes, None if it varies
+pub fn value_arity(op: &ValueOps) -> Option<usize> {
+    match op {
+        ValueOps::Not | ValueOps::Id | ValueOps::Alloc | ValueOps::Load => Some(1),
+        ValueOps::Call | ValueOps::Phi => None,
+        _ => Some(2),
+    }
+}
+
+pub fn effect_arity(op: &EffectOps) -> Option<usize> {
+    match op {
+        EffectOps::Jump | EffectOps::Nop | EffectOps::Speculate | EffectOps::Commit => Some(0),
+        EffectOps::Branch | EffectOps::Guard | EffectOps::Free => Some(1),
+        EffectOps::Store => Some(2),
+        EffectOps::Call | EffectOps::Print | EffectOps::Return => None,
+    }
+}
+
+// Checks that a transformed program is still well formed: operations have the right number of
+// arguments, used variables and labels are defined, and calls match their callee's signature.
+pub fn verify_program(prog: &Program) -> Result<()> {
+    let signatures: HashMap<&String, &Function> = prog
+        .functions
+        .iter()
+        .map(|func| (&func.name, func))
+        .collect();
+    for func in prog.functions.iter() {
+        verify_function(func, &signatures)?;
+    }
+    Ok(())
+}
+
+fn verify_function(func: &Function, signatures: &HashMap<&String, &Function>) -> Result<()> {
+    let vars = defined_vars(func);
+    let mut labels: HashSet<&String> = HashSet::new();
+    for code in func.instrs.iter() {
+        if let Code::Label { label, pos } = code {
+            if !labels.insert(label) {
+                return Err(Error::Malformed {
+                    func: func.name.clone(),
+                    msg: format!("label .{} is defined more than once", label),
+                    pos: pos.clone(),
+                });
+            }
+        }
+    }
+
+    for code in func.instrs.iter() {
+        let Code::Instruction(instr) = code else {
+            continue;
+        };
+        let malformed = |msg: String| Error::Malformed {
+            func: func.name.clone(),
+            msg,
+            pos: instruction_pos(instr),
+        };
+        let (args, funcs, label_args, arity) = match instr {
+            Instruction::Constant { .. } => continue,
+            Instruction::Value {
+                args,
+                funcs,
+                labels,
+                op,
+                ..
+            } => (args, funcs, labels, value_arity(op)),
+            Instruction::Effect {
+                args,
+                funcs,
+                labels,
+                op,
+                ..
+            } => (args, funcs, labels, effect_arity(op)),
+        };
+
+        if let Some(arity) = arity {
+            if args.len() != arity {
+                return Err(malformed(format!("`{}` takes {} arguments", instr, arity)));
+            }
+        }
+
+        match instr {
+            // phi labels name the predecessor blocks, which need not be explicit labels, and phi
+            // arguments may be undefined along some paths
+            Instruction::Value {
+                op: ValueOps::Phi, ..
+            } => {
+                if args.len() != label_args.len() {
+                    return Err(malformed(format!(
+                        "`{}` needs one label per argument",
+                        instr
+                    )));
+                }
+                continue;
+            }
+            Instruction::Value {
+                op: ValueOps::Call, ..
+            }
+            | Instruction::Effect {
+                op: EffectOps::Call,
+                ..
+            } => {
+                let callee = funcs
+                    .first()
+                    .and_then(|name| signatures.get(name))
+                    .ok_or_else(|| malformed(format!("`{}` calls an undefined function", instr)))?;
+                if callee.args.len() != args.len() {
+                    return Err(malformed(format!(
+                        "`{}` passes {} arguments to @{}, which takes {}",
+                        instr,
+                        args.len(),
+                        callee.name,
+                        callee.args.len()
+                    )));
+                }
+                if let Instruction::Value { .. } = instr {
+                    if callee.return_type.is_none() {
+                        return Err(malformed(format!(
+                            "`{}` uses the result of a void function",
+                            instr
+                        )));
+                    }
+                }
+            }
+            _ => {
+                if let Some(label) = label_args.iter().find(|&label| !labels.contains(label)) {
+                    return Err(Error::UnknownLabel {
+                        func: func.name.clone(),
+                        label: label.clone(),
+                        pos: instruction_pos(instr),
+                    });
+                }
+            }
+        }
+
+        if let Some(var) = args.iter().find(|&var| !vars.contains(var)) {
+            return Err(Error::UndefinedVariable {
+                func: func.name.clone(),
+                var: var.clone(),
+                pos: instruction_pos(instr),
+            });
+        }
+    }
+    Ok(())
+}